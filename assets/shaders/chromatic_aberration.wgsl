@@ -0,0 +1,30 @@
+struct ChromaticAberrationMaterial {
+    offset: f32,
+};
+
+@group(1) @binding(0)
+var texture: texture_2d<f32>;
+
+@group(1) @binding(1)
+var our_sampler: sampler;
+
+@group(1) @binding(2)
+var<uniform> material: ChromaticAberrationMaterial;
+
+@fragment
+fn fragment(
+    #import bevy_sprite::mesh2d_vertex_output
+) -> @location(0) vec4<f32> {
+    let texture_dimensions = textureDimensions(texture);
+    let dimensions = vec2(f32(texture_dimensions.x), f32(texture_dimensions.y));
+
+    // The channels drift apart away from the center, by `offset` pixels at the edges.
+    let direction = uv * 2.0 - 1.0;
+    let offset = direction * material.offset / dimensions;
+
+    let color = textureSample(texture, our_sampler, uv);
+    let red = textureSample(texture, our_sampler, uv + offset).r;
+    let blue = textureSample(texture, our_sampler, uv - offset).b;
+
+    return vec4(red, color.g, blue, color.a);
+}
@@ -0,0 +1,34 @@
+@group(1) @binding(0)
+var texture: texture_2d<f32>;
+
+@group(1) @binding(1)
+var our_sampler: sampler;
+
+@group(1) @binding(2)
+var<uniform> scanlines: f32;
+
+@group(1) @binding(3)
+var<uniform> curvature: f32;
+
+@fragment
+fn fragment(
+    #import bevy_sprite::mesh2d_vertex_output
+) -> @location(0) vec4<f32> {
+    // Bulge the screen outwards, more so towards the corners.
+    let centered = uv * 2.0 - 1.0;
+    let curved = centered * (1.0 + curvature * dot(centered, centered));
+    if (abs(curved.x) > 1.0 || abs(curved.y) > 1.0) {
+        return vec4(0.0, 0.0, 0.0, 1.0);
+    }
+    let curved_uv = curved * 0.5 + 0.5;
+
+    var color = textureSample(texture, our_sampler, curved_uv);
+
+    // Darken every other line of the screen image.
+    let row = u32(curved_uv.y * f32(textureDimensions(texture).y));
+    if (row % 2u == 1u) {
+        color = vec4(color.rgb * (1.0 - scanlines), color.a);
+    }
+
+    return color;
+}
@@ -0,0 +1,24 @@
+@group(1) @binding(0)
+var texture: texture_2d<f32>;
+
+@group(1) @binding(1)
+var our_sampler: sampler;
+
+@group(1) @binding(2)
+var<uniform> strength: f32;
+
+@group(1) @binding(3)
+var<uniform> depth: f32;
+
+@fragment
+fn fragment(
+    #import bevy_sprite::mesh2d_vertex_output
+) -> @location(0) vec4<f32> {
+    let color = textureSample(texture, our_sampler, uv);
+
+    // Deeper water lets through less light, and less red light most of all.
+    let darkness = depth * strength;
+    let absorption = vec3(1.0, 0.6, 0.4) * darkness;
+
+    return vec4(color.rgb * (1.0 - absorption), color.a);
+}
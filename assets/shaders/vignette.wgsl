@@ -0,0 +1,24 @@
+@group(1) @binding(0)
+var texture: texture_2d<f32>;
+
+@group(1) @binding(1)
+var our_sampler: sampler;
+
+@group(1) @binding(2)
+var<uniform> strength: f32;
+
+@group(1) @binding(3)
+var<uniform> radius: f32;
+
+@fragment
+fn fragment(
+    #import bevy_sprite::mesh2d_vertex_output
+) -> @location(0) vec4<f32> {
+    let color = textureSample(texture, our_sampler, uv);
+
+    // Distance from the center, where the corners are a little over 1.
+    let distance = length(uv * 2.0 - 1.0) / sqrt(2.0);
+    let shade = smoothstep(radius, 1.0, distance) * strength;
+
+    return vec4(color.rgb * (1.0 - shade), color.a);
+}
@@ -1,7 +1,12 @@
-#import bevy_pbr::mesh_view_bindings
-
-struct PostProcessingMaterial {
+struct WaterMaterial {
     time_since_startup: f32,
+    scale: f32,
+    distortion: f32,
+    motion1: vec2<f32>,
+    motion2: vec2<f32>,
+    tint: vec4<f32>,
+    tint_amount: f32,
+    saturation: f32,
 };
 
 @group(1) @binding(0)
@@ -11,7 +16,7 @@ var texture: texture_2d<f32>;
 var our_sampler: sampler;
 
 @group(1) @binding(2)
-var<uniform> material: PostProcessingMaterial;
+var<uniform> material: WaterMaterial;
 
 fn rand2(n: vec2<f32>) -> f32 {
     return fract(sin(dot(n, vec2<f32>(12.9898, 4.1414))) * 43758.5453);
@@ -33,34 +38,29 @@ fn noise2(coord: vec2<f32>) -> f32 {
 
 @fragment
 fn fragment(
-    @builtin(position) position: vec4<f32>,
     #import bevy_sprite::mesh2d_vertex_output
 ) -> @location(0) vec4<f32> {
-    // Get screen position with coordinates from 0 to 1
-    let uv = position.xy / vec2<f32>(view.width, view.height);
-    let scale = 0.003;
-
+    // The quad covers the whole screen image, so its uv is the position on the screen from 0 to 1.
     let texture_dimensions = textureDimensions(texture);
     let dimensions = vec2(f32(texture_dimensions.x), f32(texture_dimensions.y));
 
-    let noisecoord1 = uv * scale * dimensions;
-    let noisecoord2 = uv * scale * dimensions + 4.0;
+    let noisecoord1 = uv * material.scale * dimensions;
+    let noisecoord2 = uv * material.scale * dimensions + 4.0;
 
-    let motion1 = vec2(material.time_since_startup * 0.3, material.time_since_startup * -0.4);
-    let motion2 = vec2(material.time_since_startup * 0.1, material.time_since_startup * 0.5);
+    let motion1 = material.motion1 * material.time_since_startup;
+    let motion2 = material.motion2 * material.time_since_startup;
 
     let distort1 = vec2(noise2(noisecoord1 + motion1), noise2(noisecoord2 + motion1)) - vec2(0.5);
     let distort2 = vec2(noise2(noisecoord1 + motion2), noise2(noisecoord2 + motion2)) - vec2(0.5);
-    let distort_sum = (distort1 + distort2) / vec2(60.0);
+    let distort_sum = (distort1 + distort2) * material.distortion;
 
-    let tint = vec4(0., 100., 255., 255.);
     let color = textureSampleBias(texture, our_sampler, uv + distort_sum, 0.0);
-    var output_color = mix(color, tint, 0.0025);
+    var output_color = mix(color, material.tint, material.tint_amount);
 
     // Mixing with the tint can desaturate the colors, so add some saturation
-    output_color.r = mix(0.5, output_color.r, 1.4);
-    output_color.g = mix(0.5, output_color.g, 1.4);
-    output_color.b = mix(0.5, output_color.b, 1.4);
+    output_color.r = mix(0.5, output_color.r, material.saturation);
+    output_color.g = mix(0.5, output_color.g, material.saturation);
+    output_color.b = mix(0.5, output_color.b, material.saturation);
 
     return output_color;
 }
@@ -0,0 +1,119 @@
+use super::settings;
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+
+const SETTINGS_FILE: &str = "accessibility.json";
+
+/// How far an effect may brighten, darken or saturate the whole screen when contrast is capped.
+const CONTRAST_CAP: f32 = 0.2;
+
+/// How strongly the water warps the screen.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Distortion {
+    #[default]
+    Full,
+    Reduced,
+    Off,
+}
+
+impl Distortion {
+    /// How much of the water's distortion is kept.
+    pub fn scale(self) -> f32 {
+        match self {
+            Distortion::Full => 1.,
+            Distortion::Reduced => 0.3,
+            Distortion::Off => 0.,
+        }
+    }
+}
+
+/// How the messages shown when collecting treasure appear.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum MessageStyle {
+    /// Flashed over the whole screen for an instant.
+    #[default]
+    Flash,
+    /// Shown in a small box at the bottom of the screen for a few seconds.
+    Toast,
+}
+
+/// Options for players sensitive to motion or flashing, saved whenever they change.
+#[derive(Debug, Clone, PartialEq, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct AccessibilitySettings {
+    pub distortion: Distortion,
+    pub messages: MessageStyle,
+    /// Limits how much post processing and flashed messages may change the contrast of the whole
+    /// screen.
+    pub cap_contrast: bool,
+}
+
+impl AccessibilitySettings {
+    /// The strength of an effect that changes the contrast of the whole screen, reduced to the
+    /// cap in either direction if contrast is capped.
+    pub fn contrast(&self, amount: f32) -> f32 {
+        if self.cap_contrast {
+            amount.clamp(-CONTRAST_CAP, CONTRAST_CAP)
+        } else {
+            amount
+        }
+    }
+
+    /// How messages are shown. A flash fills the whole screen, so it is shown as a toast instead
+    /// if contrast is capped.
+    pub fn message_style(&self) -> MessageStyle {
+        if self.cap_contrast {
+            MessageStyle::Toast
+        } else {
+            self.messages
+        }
+    }
+}
+
+pub struct AccessibilityPlugin;
+
+impl Plugin for AccessibilityPlugin {
+    fn build(&self, app: &mut App) {
+        app.insert_resource(settings::load::<AccessibilitySettings>(SETTINGS_FILE))
+            .add_system(save_settings);
+    }
+}
+
+fn save_settings(accessibility: Res<AccessibilitySettings>) {
+    if accessibility.is_changed() && !accessibility.is_added() {
+        settings::save(SETTINGS_FILE, &*accessibility);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn capped_contrast_is_limited_both_ways() {
+        let settings = AccessibilitySettings {
+            cap_contrast: true,
+            ..default()
+        };
+        assert_eq!(settings.contrast(0.1), 0.1);
+        assert_eq!(settings.contrast(0.9), CONTRAST_CAP);
+        assert_eq!(settings.contrast(-0.9), -CONTRAST_CAP);
+        assert_eq!(AccessibilitySettings::default().contrast(-0.9), -0.9);
+    }
+
+    #[test]
+    fn capped_contrast_shows_flashes_as_toasts() {
+        let settings = AccessibilitySettings {
+            messages: MessageStyle::Flash,
+            cap_contrast: true,
+            ..default()
+        };
+        assert_eq!(settings.message_style(), MessageStyle::Toast);
+        assert_eq!(
+            AccessibilitySettings::default().message_style(),
+            MessageStyle::Flash
+        );
+    }
+}
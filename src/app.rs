@@ -1,5 +1,13 @@
 #[derive(Debug, Clone, PartialEq, Eq, Hash)]
 pub enum AppState {
     Loading,
+    MainMenu,
+    LoadingLevel,
     Game,
+    Paused,
+    GameOver,
+    LevelComplete,
+    Editor,
+    Accessibility,
+    Controls,
 }
@@ -1,19 +1,13 @@
-use super::map::Map;
+use super::campaign::Campaign;
+use super::post_processing::PostProcessingChain;
+use super::tileset::Tileset;
 use bevy::prelude::*;
 use bevy_asset_loader::prelude::*;
 
-#[derive(AssetCollection)]
+#[derive(AssetCollection, Default)]
 pub struct GameAssets {
-    #[asset(texture_atlas(
-        tile_size_x = 16.,
-        tile_size_y = 16.,
-        columns = 2,
-        rows = 2,
-        padding_x = 0.,
-        padding_y = 0.
-    ))]
-    #[asset(path = "textures/tiles.png")]
-    pub tile_set_atlas: Handle<TextureAtlas>,
+    #[asset(path = "textures/tiles.tileset")]
+    pub tileset: Handle<Tileset>,
 
     #[asset(texture_atlas(
         tile_size_x = 16.,
@@ -48,11 +42,25 @@ pub struct GameAssets {
     #[asset(path = "textures/portal.png")]
     pub portal_atlas: Handle<TextureAtlas>,
 
+    #[asset(texture_atlas(
+        tile_size_x = 16.,
+        tile_size_y = 16.,
+        columns = 3,
+        rows = 1,
+        padding_x = 0.,
+        padding_y = 0.
+    ))]
+    #[asset(path = "textures/checkpoint.png")]
+    pub checkpoint_atlas: Handle<TextureAtlas>,
+
     #[asset(path = "textures/coin.png")]
     pub coin_image: Handle<Image>,
 
-    #[asset(path = "maps/1.map")]
-    pub map: Handle<Map>,
+    #[asset(path = "shaders/post_processing.chain")]
+    pub post_processing: Handle<PostProcessingChain>,
+
+    #[asset(path = "maps/levels.campaign")]
+    pub campaign: Handle<Campaign>,
 
     #[asset(path = "music/Bonedust - When You Are Dead.mp3")]
     pub bgm: Handle<AudioSource>,
@@ -1,49 +1,109 @@
 use super::app::AppState;
+use super::campaign::CurrentLevel;
+use super::map::{Map, TILE_HEIGHT, TILE_WIDTH};
+use super::player::Player;
 use bevy::{
+    core_pipeline::clear_color::ClearColorConfig,
     prelude::*,
-    reflect::TypeUuid,
     render::{
         camera::{Camera, RenderTarget},
-        extract_resource::{ExtractResource, ExtractResourcePlugin},
         render_resource::*,
-        renderer::RenderQueue,
         view::RenderLayers,
-        RenderApp, RenderStage,
     },
-    sprite::{Material2d, Material2dPlugin, MaterialMesh2dBundle, RenderMaterials2d},
+    sprite::{MaterialMesh2dBundle, Mesh2dHandle},
+    window::{WindowId, WindowResized},
 };
-use std::cmp::min;
 
-const GAME_WIDTH: u32 = 640;
-const GAME_HEIGHT: u32 = 360;
+pub const GAME_WIDTH: u32 = 640;
+pub const GAME_HEIGHT: u32 = 360;
 
+/// Post processing passes render between the game camera and the camera drawing to the window.
+pub const SCREEN_CAMERA_PRIORITY: isize = 100;
+
+/// The game as seen by the game camera, before any post processing.
 #[derive(Default)]
-pub struct ScreenImage(Handle<Image>);
+pub struct ScreenImage(pub Handle<Image>);
+
+/// How the game, rendered at 640x360, is scaled up to fill the window.
+#[derive(Clone, Copy, PartialEq, Eq, Default)]
+pub enum ScaleMode {
+    /// Scales by the largest whole number that fits, leaving black bars around the rest, so
+    /// every pixel of the game is the same size.
+    #[default]
+    PixelPerfect,
+    /// Scales smoothly to fill as much of the window as possible.
+    Fit,
+}
+
+impl ScaleMode {
+    /// How many physical pixels of the window each pixel of the game covers.
+    pub fn screen_scale(self, window: &Window) -> f32 {
+        let fit = f32::min(
+            window.physical_width() as f32 / GAME_WIDTH as f32,
+            window.physical_height() as f32 / GAME_HEIGHT as f32,
+        );
+        match self {
+            // Windows smaller than the game have to be scaled down, whole numbers or not.
+            ScaleMode::PixelPerfect if fit >= 1. => fit.floor(),
+            _ => fit,
+        }
+    }
+}
+
+/// Marks the quad that draws the finished screen image to the window.
+#[derive(Component)]
+pub struct ScreenQuad;
+
+/// Marks the camera that draws the `ScreenQuad` to the window.
+#[derive(Component)]
+struct ScreenCamera;
+
+/// Marks the camera that renders the game world into the `ScreenImage`.
+#[derive(Component)]
+pub struct GameCamera;
+
+/// How the game camera follows the player around levels larger than the screen.
+pub struct FollowSettings {
+    /// Half the size of the box around the center of the screen the player can move within
+    /// without the camera moving.
+    pub dead_zone: Vec2,
+    /// How quickly the camera catches up with the player; higher is snappier.
+    pub smoothing: f32,
+}
+
+impl Default for FollowSettings {
+    fn default() -> Self {
+        FollowSettings {
+            dead_zone: Vec2::new(48., 32.),
+            smoothing: 5.,
+        }
+    }
+}
 
 pub struct CameraPlugin;
 
 impl Plugin for CameraPlugin {
     fn build(&self, app: &mut App) {
         app.init_resource::<ScreenImage>()
-            .add_plugin(Material2dPlugin::<PostProcessingMaterial>::default())
-            .add_plugin(ExtractResourcePlugin::<ExtractedTime>::default())
-            .add_system_set(SystemSet::on_enter(AppState::Game).with_system(setup_cameras));
-        app.sub_app_mut(RenderApp)
-            .add_system_to_stage(RenderStage::Prepare, prepare_post_processing_material);
+            .init_resource::<FollowSettings>()
+            .init_resource::<ScaleMode>()
+            .add_startup_system(setup_cameras)
+            .add_system(fit_screen_to_window)
+            .add_system_set(SystemSet::on_enter(AppState::Game).with_system(snap_to_player))
+            .add_system_set(SystemSet::on_update(AppState::Game).with_system(follow_player));
     }
 }
 
 fn setup_cameras(
     mut commands: Commands,
-    mut windows: ResMut<Windows>,
     mut images: ResMut<Assets<Image>>,
-    mut post_processing_materials: ResMut<Assets<PostProcessingMaterial>>,
+    mut color_materials: ResMut<Assets<ColorMaterial>>,
     mut meshes: ResMut<Assets<Mesh>>,
 ) {
-    let window = windows.get_primary_mut().unwrap();
+    // The game is always rendered at its native resolution, and only scaled up afterwards.
     let size = Extent3d {
-        width: window.physical_width(),
-        height: window.physical_height(),
+        width: GAME_WIDTH,
+        height: GAME_HEIGHT,
         ..default()
     };
 
@@ -69,41 +129,27 @@ fn setup_cameras(
 
     commands.insert_resource(ScreenImage(image_handle.clone()));
 
-    let scale = min(
-        window.physical_width() / GAME_WIDTH,
-        window.physical_height() / GAME_HEIGHT,
-    );
-
     commands
         .spawn_bundle(Camera2dBundle {
             camera: Camera {
                 target: RenderTarget::Image(image_handle.clone()),
                 ..default()
             },
-            projection: bevy::render::camera::OrthographicProjection {
-                scale: 1. / (scale as f32),
-                ..default()
-            },
             transform: Transform::from_xyz(280., 152., 999.),
             ..default()
         })
-        .insert(UiCameraConfig { show_ui: false });
+        .insert(UiCameraConfig { show_ui: false })
+        .insert(GameCamera);
 
-    // This specifies the layer used for the post processing camera, which will be attached to the post processing camera and 2d quad.
-    let post_processing_layer = RenderLayers::layer((RenderLayers::TOTAL_LAYERS - 1) as u8);
+    // This specifies the layer used for the screen camera, which will be attached to the screen camera and 2d quad.
+    let screen_layer = RenderLayers::layer((RenderLayers::TOTAL_LAYERS - 1) as u8);
 
-    let quad_handle = meshes.add(Mesh::from(shape::Quad::new(Vec2::new(
-        size.width as f32,
-        size.height as f32,
-    ))));
+    // Sized to fit the window by `fit_screen_to_window`.
+    let quad_handle = meshes.add(Mesh::from(shape::Quad::new(Vec2::ZERO)));
 
-    // This material has the texture that has been rendered.
-    let material_handle = post_processing_materials.add(PostProcessingMaterial {
-        time_since_startup: 0.,
-        source_image: image_handle,
-    });
+    // Shows the game camera's image until the post processing chain is built.
+    let material_handle = color_materials.add(ColorMaterial::from(image_handle));
 
-    // Post processing 2d quad, with material using the render texture done by the main camera, with a custom shader.
     commands
         .spawn_bundle(MaterialMesh2dBundle {
             mesh: quad_handle.into(),
@@ -114,80 +160,149 @@ fn setup_cameras(
             },
             ..default()
         })
-        .insert(post_processing_layer);
+        .insert(ScreenQuad)
+        .insert(screen_layer);
 
+    // Anything around the quad is letterboxing.
     commands
         .spawn_bundle(Camera2dBundle {
             camera: Camera {
-                priority: 1,
+                priority: SCREEN_CAMERA_PRIORITY,
                 ..default()
             },
-            projection: bevy::render::camera::OrthographicProjection {
-                scale: 1. / (scale as f32),
-                ..default()
+            camera_2d: Camera2d {
+                clear_color: ClearColorConfig::Custom(Color::BLACK),
             },
-            transform: Transform::from_xyz(280., 152., 999.),
+            transform: Transform::from_xyz(0., 0., 999.),
             ..default()
         })
-        .insert(post_processing_layer);
+        .insert(ScreenCamera)
+        .insert(screen_layer);
 }
 
-fn prepare_post_processing_material(
-    materials: Res<RenderMaterials2d<PostProcessingMaterial>>,
-    query: Query<&Handle<PostProcessingMaterial>>,
-    time: Res<ExtractedTime>,
-    render_queue: Res<RenderQueue>,
+/// Sizes the quad showing the game to the window.
+fn fit_screen_to_window(
+    mut resized_events: EventReader<WindowResized>,
+    windows: Res<Windows>,
+    scale_mode: Res<ScaleMode>,
+    mut meshes: ResMut<Assets<Mesh>>,
+    quad_query: Query<&Mesh2dHandle, With<ScreenQuad>>,
+    mut projection_query: Query<&mut OrthographicProjection, With<ScreenCamera>>,
 ) {
-    for handle in &query {
-        if let Some(material) = materials.get(handle) {
-            for binding in material.bindings.iter() {
-                if let OwnedBindingResource::Buffer(cur_buffer) = binding {
-                    let mut buffer = encase::UniformBuffer::new(Vec::new());
-                    buffer
-                        .write(&PostProcessingMaterialUniformData {
-                            time_since_startup: time.time_since_startup,
-                        })
-                        .unwrap();
-                    render_queue.write_buffer(cur_buffer, 0, buffer.as_ref());
-                }
-            }
-        }
+    let resized = resized_events
+        .iter()
+        .any(|event| event.id == WindowId::primary());
+    if !resized && !scale_mode.is_changed() {
+        return;
+    }
+
+    let window = windows.get_primary().unwrap();
+    let scale = scale_mode.screen_scale(window);
+    for mesh in quad_query.iter() {
+        *meshes.get_mut(&mesh.0).unwrap() = Mesh::from(shape::Quad::new(
+            Vec2::new(GAME_WIDTH as f32, GAME_HEIGHT as f32) * scale,
+        ));
     }
-}
 
-/// Our custom post processing material
-#[derive(AsBindGroup, TypeUuid, Clone)]
-#[uuid = "1c4b51b9-bf23-4fd5-8e80-febc028fdb4e"]
-struct PostProcessingMaterial {
-    /// In this example, this image will be the result of the main camera.
-    #[texture(0)]
-    #[sampler(1)]
-    source_image: Handle<Image>,
-    #[uniform(2)]
-    time_since_startup: f32,
+    // Work in physical pixels, so the quad lines up with the pixels of the window.
+    for mut projection in projection_query.iter_mut() {
+        projection.scale = 1. / window.scale_factor() as f32;
+    }
 }
 
-impl Material2d for PostProcessingMaterial {
-    fn fragment_shader() -> ShaderRef {
-        "shaders/water.wgsl".into()
+/// Converts the cursor position in the window to a point in the world seen by the game camera,
+/// or `None` if the cursor is outside the window or over the letterboxing.
+pub fn cursor_to_world(
+    window: &Window,
+    scale_mode: ScaleMode,
+    camera_transform: &GlobalTransform,
+    projection: &OrthographicProjection,
+) -> Option<Vec2> {
+    let scale = scale_mode.screen_scale(window);
+    if scale <= 0. {
+        return None;
     }
+
+    let cursor = window.cursor_position()? * window.scale_factor() as f32;
+    let window_size = Vec2::new(
+        window.physical_width() as f32,
+        window.physical_height() as f32,
+    );
+    let screen_position = (cursor - window_size / 2.) / scale;
+    let half_screen = Vec2::new(GAME_WIDTH as f32, GAME_HEIGHT as f32) / 2.;
+    if screen_position.abs().cmpgt(half_screen).any() {
+        return None;
+    }
+    Some(camera_transform.translation().truncate() + screen_position * projection.scale)
 }
 
-#[derive(Clone, ShaderType)]
-struct PostProcessingMaterialUniformData {
-    time_since_startup: f32,
+/// Keeps the view inside the map, centering it along any axis where the map is smaller than the
+/// screen.
+pub fn clamp_to_map(position: Vec2, map: &Map, projection: &OrthographicProjection) -> Vec2 {
+    let half_view = Vec2::new(GAME_WIDTH as f32, GAME_HEIGHT as f32) * projection.scale / 2.;
+    // Tiles are centered on their position, so the map starts half a tile early.
+    let min = Vec2::new(-TILE_WIDTH / 2., -TILE_HEIGHT / 2.);
+    let max = min
+        + Vec2::new(
+            map.width as f32 * TILE_WIDTH,
+            map.height as f32 * TILE_HEIGHT,
+        );
+
+    let clamp_axis = |position: f32, min: f32, max: f32, half_view: f32| {
+        if max - min <= half_view * 2. {
+            (min + max) / 2.
+        } else {
+            position.clamp(min + half_view, max - half_view)
+        }
+    };
+    Vec2::new(
+        clamp_axis(position.x, min.x, max.x, half_view.x),
+        clamp_axis(position.y, min.y, max.y, half_view.y),
+    )
 }
 
-struct ExtractedTime {
-    time_since_startup: f32,
+fn snap_to_player(
+    mut camera_query: Query<(&mut Transform, &OrthographicProjection), With<GameCamera>>,
+    maps: Res<Assets<Map>>,
+    level: Res<CurrentLevel>,
+) {
+    // The player has not been spawned yet, so start from where they will be.
+    let map = maps.get(&level.map).unwrap();
+    let spawn = Vec2::new(
+        TILE_WIDTH * map.player_spawn.0 as f32,
+        TILE_HEIGHT * map.player_spawn.1 as f32,
+    );
+    let (mut transform, projection) = camera_query.single_mut();
+    let position = clamp_to_map(spawn, map, projection);
+    transform.translation.x = position.x;
+    transform.translation.y = position.y;
 }
 
-impl ExtractResource for ExtractedTime {
-    type Source = Time;
+fn follow_player(
+    player_query: Query<&Transform, (With<Player>, Without<GameCamera>)>,
+    mut camera_query: Query<(&mut Transform, &OrthographicProjection), With<GameCamera>>,
+    maps: Res<Assets<Map>>,
+    level: Res<CurrentLevel>,
+    settings: Res<FollowSettings>,
+    time: Res<Time>,
+) {
+    let player = match player_query.iter().next() {
+        Some(player_transform) => player_transform.translation.truncate(),
+        None => return,
+    };
+    let map = maps.get(&level.map).unwrap();
+    let (mut transform, projection) = camera_query.single_mut();
+    let camera = transform.translation.truncate();
+
+    // Only move far enough to bring the player back inside the dead zone.
+    let offset = player - camera;
+    let excess = offset - offset.clamp(-settings.dead_zone, settings.dead_zone);
+    let target = clamp_to_map(camera + excess, map, projection);
 
-    fn extract_resource(time: &Self::Source) -> Self {
-        ExtractedTime {
-            time_since_startup: time.seconds_since_startup() as f32,
-        }
-    }
+    let position = camera.lerp(
+        target,
+        1. - (-settings.smoothing * time.delta_seconds()).exp(),
+    );
+    transform.translation.x = position.x;
+    transform.translation.y = position.y;
 }
@@ -0,0 +1,165 @@
+use super::app::AppState;
+use super::assets::GameAssets;
+use super::map::{Map, MapLoadErrors};
+use bevy::{
+    asset::{AssetLoader, AssetPath, LoadContext, LoadState, LoadedAsset},
+    prelude::*,
+    reflect::TypeUuid,
+    utils::BoxedFuture,
+};
+use std::str;
+
+/// An ordered list of levels, loaded from a `.campaign` manifest listing one map path per line
+/// relative to the manifest. Blank lines and lines starting with `#` are ignored.
+#[derive(Debug, TypeUuid)]
+#[uuid = "3f1c4a0e-6a2b-4c55-9d8e-2b7f0f9c5e41"]
+pub struct Campaign {
+    pub levels: Vec<Handle<Map>>,
+}
+
+pub struct CampaignLoader {
+    errors: MapLoadErrors,
+}
+
+impl FromWorld for CampaignLoader {
+    fn from_world(world: &mut World) -> Self {
+        CampaignLoader {
+            errors: world
+                .get_resource_or_insert_with(MapLoadErrors::default)
+                .clone(),
+        }
+    }
+}
+
+impl AssetLoader for CampaignLoader {
+    fn load<'a>(
+        &'a self,
+        bytes: &'a [u8],
+        load_context: &'a mut LoadContext,
+    ) -> BoxedFuture<'a, Result<(), bevy::asset::Error>> {
+        Box::pin(async move {
+            let manifest = match str::from_utf8(bytes) {
+                Ok(manifest) => manifest,
+                Err(err) => {
+                    self.errors
+                        .push(format!("{}: {}", load_context.path().display(), err));
+                    return Err(err.into());
+                }
+            };
+
+            let dir = load_context.path().parent().unwrap().to_path_buf();
+            let paths = manifest
+                .lines()
+                .map(str::trim)
+                .filter(|line| !line.is_empty() && !line.starts_with('#'))
+                .map(|line| AssetPath::from(dir.join(line)))
+                .collect::<Vec<AssetPath>>();
+
+            if paths.is_empty() {
+                let err = format!(
+                    "{}: campaign lists no levels",
+                    load_context.path().display()
+                );
+                self.errors.push(err.clone());
+                return Err(bevy::asset::Error::msg(err));
+            }
+
+            let campaign = Campaign {
+                levels: paths
+                    .iter()
+                    .map(|path| load_context.get_handle(path.clone()))
+                    .collect(),
+            };
+            load_context.set_default_asset(LoadedAsset::new(campaign).with_dependencies(paths));
+            Ok(())
+        })
+    }
+
+    fn extensions(&self) -> &[&str] {
+        &["campaign"]
+    }
+}
+
+/// The level currently being played.
+pub struct CurrentLevel {
+    pub index: usize,
+    pub map: Handle<Map>,
+}
+
+impl CurrentLevel {
+    /// Starts at the beginning of the campaign.
+    pub fn first(campaign: &Campaign) -> Self {
+        CurrentLevel {
+            index: 0,
+            map: campaign.levels[0].clone(),
+        }
+    }
+
+    /// Starts at the level with the given index, if the campaign has that many levels.
+    pub fn at(campaign: &Campaign, index: usize) -> Option<Self> {
+        campaign.levels.get(index).map(|map| CurrentLevel {
+            index,
+            map: map.clone(),
+        })
+    }
+
+    /// Moves on to the next level of the campaign, returning `false` if this was the last one.
+    pub fn advance(&mut self, campaign: &Campaign) -> bool {
+        match campaign.levels.get(self.index + 1) {
+            Some(map) => {
+                self.index += 1;
+                self.map = map.clone();
+                true
+            }
+            None => false,
+        }
+    }
+
+    pub fn is_last(&self, campaign: &Campaign) -> bool {
+        self.index + 1 >= campaign.levels.len()
+    }
+}
+
+/// Marks entities that belong to the current level and are despawned when leaving it.
+#[derive(Component)]
+pub struct LevelEntity;
+
+pub struct CampaignPlugin;
+
+impl Plugin for CampaignPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_asset::<Campaign>()
+            .init_asset_loader::<CampaignLoader>()
+            .add_system_set(SystemSet::on_exit(AppState::Loading).with_system(start_campaign))
+            .add_system_set(
+                SystemSet::on_update(AppState::LoadingLevel).with_system(wait_for_level),
+            )
+            .add_system_set(SystemSet::on_exit(AppState::Game).with_system(despawn_level));
+    }
+}
+
+fn start_campaign(
+    mut commands: Commands,
+    campaigns: Res<Assets<Campaign>>,
+    game_assets: Res<GameAssets>,
+) {
+    let campaign = campaigns.get(&game_assets.campaign).unwrap();
+    commands.insert_resource(CurrentLevel::first(campaign));
+}
+
+fn wait_for_level(
+    mut state: ResMut<State<AppState>>,
+    asset_server: Res<AssetServer>,
+    level: Res<CurrentLevel>,
+) {
+    // Failed maps are reported by the map error screen, so just keep waiting here.
+    if asset_server.get_load_state(&level.map) == LoadState::Loaded {
+        state.set(AppState::Game).unwrap();
+    }
+}
+
+fn despawn_level(mut commands: Commands, query: Query<Entity, With<LevelEntity>>) {
+    for entity in query.iter() {
+        commands.entity(entity).despawn_recursive();
+    }
+}
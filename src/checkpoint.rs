@@ -0,0 +1,242 @@
+use super::animation::{Animation, AnimationData, AnimationState};
+use super::app::AppState;
+use super::assets::GameAssets;
+use super::campaign::{CurrentLevel, LevelEntity};
+use super::health::RespawnPoint;
+use super::map::{Map, MapEntity, MapReloaded, TILE_HEIGHT, TILE_WIDTH};
+use super::player::Player;
+use super::tick::{self, TickStage};
+use super::treasure::Treasure;
+use benimator::Frame;
+use bevy::prelude::*;
+use bevy_rapier2d::prelude::*;
+use std::time::Duration;
+
+/// A place the player comes back to after losing a life, once they have touched it. Only the last
+/// one touched is lit.
+#[derive(Component)]
+pub struct Checkpoint {
+    pub cell: (u32, u32),
+    pub lit: bool,
+}
+
+pub struct CheckpointAnimations {
+    lit: Handle<AnimationData>,
+    unlit: Handle<AnimationData>,
+}
+
+pub struct CheckpointPlugin;
+
+impl Plugin for CheckpointPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_system_set(SystemSet::on_enter(AppState::Game).with_system(setup_checkpoints))
+            .add_system_set(SystemSet::on_update(AppState::Game).with_system(reload_checkpoints));
+        tick::add_systems(
+            app,
+            TickStage::Gameplay,
+            SystemSet::new().with_system(touch_checkpoint),
+        );
+    }
+}
+
+fn setup_checkpoints(
+    mut commands: Commands,
+    game_assets: Res<GameAssets>,
+    maps: Res<Assets<Map>>,
+    level: Res<CurrentLevel>,
+    mut animations: ResMut<Assets<AnimationData>>,
+) {
+    let map = maps.get(&level.map).unwrap();
+
+    let unlit = AnimationData(benimator::Animation::from_frames(vec![Frame::new(
+        0,
+        Duration::from_millis(250),
+    )]));
+    let unlit_handle = animations.add(unlit);
+
+    let lit = AnimationData(benimator::Animation::from_frames(vec![
+        Frame::new(1, Duration::from_millis(250)),
+        Frame::new(2, Duration::from_millis(250)),
+    ]));
+    let lit_handle = animations.add(lit);
+
+    commands.insert_resource(CheckpointAnimations {
+        lit: lit_handle,
+        unlit: unlit_handle.clone(),
+    });
+
+    spawn_checkpoints(&mut commands, map, &game_assets, unlit_handle);
+}
+
+fn reload_checkpoints(
+    mut commands: Commands,
+    mut map_reloaded_events: EventReader<MapReloaded>,
+    game_assets: Res<GameAssets>,
+    maps: Res<Assets<Map>>,
+    level: Res<CurrentLevel>,
+    checkpoint_animations: Res<CheckpointAnimations>,
+) {
+    if map_reloaded_events.iter().count() == 0 {
+        return;
+    }
+    let map = maps.get(&level.map).unwrap();
+    spawn_checkpoints(
+        &mut commands,
+        map,
+        &game_assets,
+        checkpoint_animations.unlit.clone(),
+    );
+}
+
+fn spawn_checkpoints(
+    commands: &mut Commands,
+    map: &Map,
+    game_assets: &GameAssets,
+    unlit: Handle<AnimationData>,
+) {
+    for &checkpoint in map.checkpoints.iter() {
+        commands
+            .spawn_bundle(SpriteSheetBundle {
+                texture_atlas: game_assets.checkpoint_atlas.clone(),
+                transform: Transform::from_translation(Vec3::new(
+                    TILE_WIDTH * checkpoint.0 as f32,
+                    TILE_HEIGHT * checkpoint.1 as f32,
+                    1.,
+                )),
+                sprite: TextureAtlasSprite {
+                    index: 0,
+                    ..default()
+                },
+                ..default()
+            })
+            .insert(Animation(unlit.clone()))
+            .insert(AnimationState::default())
+            .insert(Collider::cuboid(7., 7.))
+            .insert(Sensor)
+            .insert(ActiveEvents::COLLISION_EVENTS)
+            .insert(Checkpoint {
+                cell: checkpoint,
+                lit: false,
+            })
+            .insert(LevelEntity)
+            .insert(MapEntity);
+    }
+}
+
+/// Makes a checkpoint the player touches where they respawn, remembering the treasure still left
+/// so that anything collected afterwards is put back if they lose a life.
+#[allow(clippy::too_many_arguments)]
+fn touch_checkpoint(
+    player_query: Query<Entity, With<Player>>,
+    mut checkpoint_query: Query<(Entity, &mut Checkpoint, &mut Animation, &mut AnimationState)>,
+    treasure_query: Query<&Treasure>,
+    mut collision_events: EventReader<CollisionEvent>,
+    mut respawn_point: ResMut<RespawnPoint>,
+    checkpoint_animations: Res<CheckpointAnimations>,
+    game_assets: Res<GameAssets>,
+    audio: Res<Audio>,
+) {
+    for event in collision_events.iter() {
+        if let CollisionEvent::Started(h1, h2, _flags) = event {
+            let player = player_query.single();
+            let touched = if h1 == &player {
+                *h2
+            } else if h2 == &player {
+                *h1
+            } else {
+                continue;
+            };
+            let cell = match checkpoint_query.get(touched) {
+                Ok((_, checkpoint, _, _)) => checkpoint.cell,
+                Err(_) => continue,
+            };
+
+            respawn_point.cell = cell;
+            respawn_point.treasures = treasure_query
+                .iter()
+                .map(|treasure| treasure.cell)
+                .collect();
+
+            for (entity, mut checkpoint, mut animation, mut animation_state) in
+                checkpoint_query.iter_mut()
+            {
+                let lit = entity == touched;
+                if checkpoint.lit == lit {
+                    continue;
+                }
+                checkpoint.lit = lit;
+                animation.0 = if lit {
+                    checkpoint_animations.lit.clone()
+                } else {
+                    checkpoint_animations.unlit.clone()
+                };
+                animation_state.reset();
+                if lit {
+                    audio.play(game_assets.portal_sfx.clone());
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::harness::{Harness, SWIM_RIGHT};
+    use crate::health::Lives;
+    use crate::oxygen::Oxygen;
+    use crate::portal::Portal;
+
+    const LEVEL: &str = "1 1 1 1 1 1 1 1 1
+                         1 0 0 0 0 0 0 0 1
+                         1 P 0 C 0 T 0 X 1
+                         1 1 1 1 1 1 1 1 1";
+
+    fn is_lit(world: &mut World) -> bool {
+        world.query::<&Checkpoint>().single(world).lit
+    }
+
+    fn drown(harness: &mut Harness) {
+        harness
+            .app
+            .world
+            .query::<&mut Oxygen>()
+            .single_mut(&mut harness.app.world)
+            .0 = 0.;
+        harness.run(2, default());
+    }
+
+    #[test]
+    fn touching_checkpoint_moves_respawn_point() {
+        let mut harness = Harness::new(LEVEL);
+        assert!(!is_lit(&mut harness.app.world));
+        harness.run_until(120, SWIM_RIGHT, is_lit);
+        assert_eq!(harness.app.world.resource::<RespawnPoint>().cell, (3, 1));
+
+        harness.run(30, SWIM_RIGHT);
+        drown(&mut harness);
+        assert_eq!(
+            harness.app.world.resource::<Lives>().0,
+            Lives::default().0 - 1
+        );
+        let checkpoint = harness.single::<&Transform, With<Checkpoint>>().translation;
+        let player = harness.single::<&Transform, With<Player>>().translation;
+        assert!(player.truncate().distance(checkpoint.truncate()) < 1.);
+        assert!(is_lit(&mut harness.app.world));
+    }
+
+    #[test]
+    fn treasure_collected_after_checkpoint_comes_back_on_respawn() {
+        let mut harness = Harness::new(LEVEL);
+        harness.run_until(120, SWIM_RIGHT, is_lit);
+        harness.run_until(240, SWIM_RIGHT, |world| {
+            world.query::<&Treasure>().iter(world).count() == 0
+        });
+        harness.run(2, SWIM_RIGHT);
+        assert!(harness.single::<&Portal, ()>().opened);
+
+        drown(&mut harness);
+        assert_eq!(harness.single::<&Treasure, ()>().cell, (5, 1));
+        assert!(!harness.single::<&Portal, ()>().opened);
+    }
+}
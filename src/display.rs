@@ -0,0 +1,39 @@
+use super::camera::ScaleMode;
+use bevy::{prelude::*, window::WindowMode};
+
+const SCALE_MODE_KEY: KeyCode = KeyCode::F10;
+const WINDOW_MODE_KEY: KeyCode = KeyCode::F11;
+
+pub struct DisplayPlugin;
+
+impl Plugin for DisplayPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_system(toggle_scale_mode)
+            .add_system(cycle_window_mode);
+    }
+}
+
+/// The window mode to switch to from `mode`, cycling windowed, borderless and fullscreen.
+fn next_window_mode(mode: WindowMode) -> WindowMode {
+    match mode {
+        WindowMode::Windowed => WindowMode::BorderlessFullscreen,
+        WindowMode::BorderlessFullscreen => WindowMode::Fullscreen,
+        WindowMode::SizedFullscreen | WindowMode::Fullscreen => WindowMode::Windowed,
+    }
+}
+
+fn cycle_window_mode(keyboard_input: Res<Input<KeyCode>>, mut windows: ResMut<Windows>) {
+    if keyboard_input.just_pressed(WINDOW_MODE_KEY) {
+        let window = windows.get_primary_mut().unwrap();
+        window.set_mode(next_window_mode(window.mode()));
+    }
+}
+
+fn toggle_scale_mode(keyboard_input: Res<Input<KeyCode>>, mut scale_mode: ResMut<ScaleMode>) {
+    if keyboard_input.just_pressed(SCALE_MODE_KEY) {
+        *scale_mode = match *scale_mode {
+            ScaleMode::PixelPerfect => ScaleMode::Fit,
+            ScaleMode::Fit => ScaleMode::PixelPerfect,
+        };
+    }
+}
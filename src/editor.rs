@@ -0,0 +1,441 @@
+use super::app::AppState;
+use super::assets::GameAssets;
+use super::camera::{clamp_to_map, cursor_to_world, GameCamera, ScaleMode};
+use super::campaign::CurrentLevel;
+use super::map::{file_hash, tile_color, Map, Tile, TILE_HEIGHT, TILE_WIDTH};
+use super::tileset::Tileset;
+use bevy::{
+    asset::{AssetServerSettings, FileAssetIo},
+    prelude::*,
+};
+use std::fs;
+
+#[derive(Clone, Copy, PartialEq)]
+enum EditorTool {
+    Wall,
+    Water,
+    Player,
+    Portal,
+    Treasure,
+    Enemy,
+    Air,
+    Checkpoint,
+}
+
+impl EditorTool {
+    fn name(&self) -> &'static str {
+        match self {
+            EditorTool::Wall => "WALL",
+            EditorTool::Water => "WATER",
+            EditorTool::Player => "PLAYER",
+            EditorTool::Portal => "PORTAL",
+            EditorTool::Treasure => "TREASURE",
+            EditorTool::Enemy => "PIRANHA",
+            EditorTool::Air => "AIR POCKET",
+            EditorTool::Checkpoint => "CHECKPOINT",
+        }
+    }
+}
+
+const TOOL_KEYS: [(KeyCode, EditorTool); 8] = [
+    (KeyCode::Key1, EditorTool::Wall),
+    (KeyCode::Key2, EditorTool::Water),
+    (KeyCode::Key3, EditorTool::Player),
+    (KeyCode::Key4, EditorTool::Portal),
+    (KeyCode::Key5, EditorTool::Treasure),
+    (KeyCode::Key6, EditorTool::Enemy),
+    (KeyCode::Key7, EditorTool::Air),
+    (KeyCode::Key8, EditorTool::Checkpoint),
+];
+
+// How fast the arrow keys scroll around maps larger than the screen, in pixels per second
+const PAN_SPEED: f32 = 200.;
+
+const HELP: &str = "LEVEL EDITOR\n\
+    1 WALL  2 WATER  3 PLAYER  4 PORTAL  5 TREASURE  6 PIRANHA  7 AIR POCKET  8 CHECKPOINT\n\
+    LEFT CLICK: PLACE  RIGHT CLICK: ERASE  ARROW KEYS: SCROLL\n\
+    TAB: PLAY-TEST  CTRL+S: SAVE  ESC: MAIN MENU\n";
+
+struct EditorState {
+    tool: EditorTool,
+}
+
+/// Present while play-testing a level from the editor, so the game knows to return to it.
+pub struct PlayTest;
+
+/// Marks entities that only exist while the editor is open.
+#[derive(Component)]
+struct EditorEntity;
+
+/// Marks the sprites showing the map being edited, which are redrawn whenever it changes.
+#[derive(Component)]
+struct EditorCell;
+
+#[derive(Component)]
+struct EditorHud;
+
+pub struct EditorPlugin;
+
+impl Plugin for EditorPlugin {
+    fn build(&self, app: &mut App) {
+        app.insert_resource(EditorState {
+            tool: EditorTool::Wall,
+        })
+        .add_system_set(
+            SystemSet::on_enter(AppState::Editor)
+                .with_system(setup_editor)
+                .with_system(draw_map),
+        )
+        .add_system_set(
+            SystemSet::on_update(AppState::Editor)
+                .with_system(redraw_map)
+                .with_system(select_tool)
+                .with_system(pan_camera)
+                .with_system(paint)
+                .with_system(save_map)
+                .with_system(leave_editor),
+        )
+        .add_system_set(SystemSet::on_exit(AppState::Editor).with_system(despawn_editor))
+        .add_system_set(SystemSet::on_update(AppState::Game).with_system(return_to_editor))
+        .add_system_set(SystemSet::on_enter(AppState::MainMenu).with_system(end_play_test));
+    }
+}
+
+fn setup_editor(
+    mut commands: Commands,
+    game_assets: Res<GameAssets>,
+    editor_state: Res<EditorState>,
+) {
+    let style = TextStyle {
+        font: game_assets.ui_font.clone(),
+        font_size: 30.0,
+        color: Color::WHITE,
+    };
+    commands
+        .spawn_bundle(
+            TextBundle::from_sections([
+                TextSection::new(HELP, style.clone()),
+                TextSection::new(
+                    format!("TOOL: {}\n", editor_state.tool.name()),
+                    style.clone(),
+                ),
+                TextSection::new("", style),
+            ])
+            .with_style(Style {
+                position_type: PositionType::Absolute,
+                position: UiRect {
+                    top: Val::Px(20.0),
+                    left: Val::Px(20.0),
+                    ..default()
+                },
+                ..default()
+            }),
+        )
+        .insert(EditorHud)
+        .insert(EditorEntity);
+}
+
+fn draw_map(
+    mut commands: Commands,
+    maps: Res<Assets<Map>>,
+    tilesets: Res<Assets<Tileset>>,
+    level: Res<CurrentLevel>,
+    game_assets: Res<GameAssets>,
+) {
+    if let Some(map) = maps.get(&level.map) {
+        let tileset = tilesets.get(&game_assets.tileset).unwrap();
+        spawn_cells(&mut commands, map, tileset, &game_assets);
+    }
+}
+
+fn redraw_map(
+    mut commands: Commands,
+    mut map_events: EventReader<AssetEvent<Map>>,
+    cell_query: Query<Entity, With<EditorCell>>,
+    maps: Res<Assets<Map>>,
+    tilesets: Res<Assets<Tileset>>,
+    level: Res<CurrentLevel>,
+    game_assets: Res<GameAssets>,
+) {
+    let changed = map_events.iter().any(|event| match event {
+        AssetEvent::Created { handle } | AssetEvent::Modified { handle } => handle == &level.map,
+        AssetEvent::Removed { .. } => false,
+    });
+    if !changed {
+        return;
+    }
+
+    for entity in cell_query.iter() {
+        commands.entity(entity).despawn_recursive();
+    }
+    if let Some(map) = maps.get(&level.map) {
+        let tileset = tilesets.get(&game_assets.tileset).unwrap();
+        spawn_cells(&mut commands, map, tileset, &game_assets);
+    }
+}
+
+fn spawn_cells(commands: &mut Commands, map: &Map, tileset: &Tileset, game_assets: &GameAssets) {
+    let translation = |position: (u32, u32), z: f32| {
+        Transform::from_translation(Vec3::new(
+            TILE_WIDTH * position.0 as f32,
+            TILE_HEIGHT * position.1 as f32,
+            z,
+        ))
+    };
+
+    for i in 0..map.height {
+        for j in 0..map.width {
+            commands
+                .spawn_bundle(SpriteSheetBundle {
+                    texture_atlas: tileset.atlas.clone(),
+                    transform: translation((j as u32, i as u32), 0.),
+                    sprite: TextureAtlasSprite {
+                        index: tileset.index(map, j, i),
+                        color: tile_color(map.tiles[i][j]),
+                        ..default()
+                    },
+                    ..default()
+                })
+                .insert(EditorCell)
+                .insert(EditorEntity);
+        }
+    }
+
+    let markers = [
+        (map.player_spawn, game_assets.player_atlas.clone()),
+        (map.portal_spawn, game_assets.portal_atlas.clone()),
+    ]
+    .into_iter()
+    .chain(
+        map.enemies
+            .iter()
+            .map(|&enemy| (enemy, game_assets.piranha_atlas.clone())),
+    )
+    .chain(
+        map.checkpoints
+            .iter()
+            .map(|&checkpoint| (checkpoint, game_assets.checkpoint_atlas.clone())),
+    );
+    for (position, texture_atlas) in markers {
+        commands
+            .spawn_bundle(SpriteSheetBundle {
+                texture_atlas,
+                transform: translation(position, 1.),
+                ..default()
+            })
+            .insert(EditorCell)
+            .insert(EditorEntity);
+    }
+
+    for &treasure in map.treasures.iter() {
+        commands
+            .spawn_bundle(SpriteBundle {
+                texture: game_assets.coin_image.clone(),
+                transform: translation(treasure, 1.),
+                ..default()
+            })
+            .insert(EditorCell)
+            .insert(EditorEntity);
+    }
+}
+
+fn select_tool(
+    keyboard_input: Res<Input<KeyCode>>,
+    mut editor_state: ResMut<EditorState>,
+    mut hud_query: Query<&mut Text, With<EditorHud>>,
+) {
+    for (key, tool) in TOOL_KEYS {
+        if keyboard_input.just_pressed(key) {
+            editor_state.tool = tool;
+            for mut text in hud_query.iter_mut() {
+                text.sections[1].value = format!("TOOL: {}\n", tool.name());
+            }
+        }
+    }
+}
+
+fn pan_camera(
+    keyboard_input: Res<Input<KeyCode>>,
+    mut camera_query: Query<(&mut Transform, &OrthographicProjection), With<GameCamera>>,
+    maps: Res<Assets<Map>>,
+    level: Res<CurrentLevel>,
+    time: Res<Time>,
+) {
+    let map = match maps.get(&level.map) {
+        Some(map) => map,
+        None => return,
+    };
+    let mut direction = Vec2::ZERO;
+    if keyboard_input.pressed(KeyCode::Left) {
+        direction.x -= 1.;
+    }
+    if keyboard_input.pressed(KeyCode::Right) {
+        direction.x += 1.;
+    }
+    if keyboard_input.pressed(KeyCode::Down) {
+        direction.y -= 1.;
+    }
+    if keyboard_input.pressed(KeyCode::Up) {
+        direction.y += 1.;
+    }
+
+    // Clamp even when not scrolling, in case the map was resized.
+    let (mut transform, projection) = camera_query.single_mut();
+    let position = clamp_to_map(
+        transform.translation.truncate() + direction * PAN_SPEED * time.delta_seconds(),
+        map,
+        projection,
+    );
+    transform.translation.x = position.x;
+    transform.translation.y = position.y;
+}
+
+fn paint(
+    mouse_input: Res<Input<MouseButton>>,
+    windows: Res<Windows>,
+    scale_mode: Res<ScaleMode>,
+    camera_query: Query<(&GlobalTransform, &OrthographicProjection), With<GameCamera>>,
+    editor_state: Res<EditorState>,
+    mut maps: ResMut<Assets<Map>>,
+    level: Res<CurrentLevel>,
+) {
+    // Tiles can be painted by dragging, but markers are placed one click at a time.
+    let tool = if mouse_input.pressed(MouseButton::Right) {
+        EditorTool::Water
+    } else if mouse_input.pressed(MouseButton::Left) {
+        editor_state.tool
+    } else {
+        return;
+    };
+    let is_marker = !matches!(tool, EditorTool::Wall | EditorTool::Water | EditorTool::Air);
+    if is_marker && !mouse_input.just_pressed(MouseButton::Left) {
+        return;
+    }
+
+    let window = windows.get_primary().unwrap();
+    let (camera_transform, projection) = camera_query.single();
+    let cursor = match cursor_to_world(window, *scale_mode, camera_transform, projection) {
+        Some(cursor) => cursor,
+        None => return,
+    };
+    let map = match maps.get(&level.map) {
+        Some(map) => map,
+        None => return,
+    };
+
+    // Tiles are centered on their position, so round to the nearest one.
+    let column = (cursor.x / TILE_WIDTH).round();
+    let row = (cursor.y / TILE_HEIGHT).round();
+    if column < 0. || row < 0. || column >= map.width as f32 || row >= map.height as f32 {
+        return;
+    }
+    let position = (column as u32, row as u32);
+
+    // Every cell holds a single thing, and the player spawn and portal can only be moved.
+    let occupied_by_spawn = position == map.player_spawn || position == map.portal_spawn;
+    let unchanged = match tool {
+        EditorTool::Wall => map.tiles[row as usize][column as usize] == Tile::Wall,
+        EditorTool::Water => {
+            map.tiles[row as usize][column as usize] == Tile::Empty
+                && !map.treasures.contains(&position)
+                && !map.enemies.contains(&position)
+                && !map.checkpoints.contains(&position)
+        }
+        EditorTool::Player => position == map.player_spawn,
+        EditorTool::Portal => position == map.portal_spawn,
+        EditorTool::Treasure => map.treasures.contains(&position),
+        EditorTool::Enemy => map.enemies.contains(&position),
+        EditorTool::Air => map.tiles[row as usize][column as usize] == Tile::Air,
+        EditorTool::Checkpoint => map.checkpoints.contains(&position),
+    };
+    if unchanged || occupied_by_spawn {
+        return;
+    }
+
+    let map = maps.get_mut(&level.map).unwrap();
+    map.tiles[row as usize][column as usize] = Tile::Empty;
+    map.treasures.retain(|&treasure| treasure != position);
+    map.enemies.retain(|&enemy| enemy != position);
+    map.checkpoints.retain(|&checkpoint| checkpoint != position);
+    match tool {
+        EditorTool::Wall => map.tiles[row as usize][column as usize] = Tile::Wall,
+        EditorTool::Water => {}
+        EditorTool::Player => map.player_spawn = position,
+        EditorTool::Portal => map.portal_spawn = position,
+        EditorTool::Treasure => map.treasures.push(position),
+        EditorTool::Enemy => map.enemies.push(position),
+        EditorTool::Air => map.tiles[row as usize][column as usize] = Tile::Air,
+        EditorTool::Checkpoint => map.checkpoints.push(position),
+    }
+    // The edited map no longer matches its file, so identify it by what saving it would write.
+    map.file_hash = file_hash(map.to_string().as_bytes());
+}
+
+fn save_map(
+    keyboard_input: Res<Input<KeyCode>>,
+    asset_server: Res<AssetServer>,
+    asset_server_settings: Res<AssetServerSettings>,
+    maps: Res<Assets<Map>>,
+    level: Res<CurrentLevel>,
+    mut hud_query: Query<&mut Text, With<EditorHud>>,
+) {
+    let ctrl = keyboard_input.any_pressed([KeyCode::LControl, KeyCode::RControl]);
+    if !ctrl || !keyboard_input.just_pressed(KeyCode::S) {
+        return;
+    }
+
+    let (map, asset_path) = match (
+        maps.get(&level.map),
+        asset_server.get_handle_path(&level.map),
+    ) {
+        (Some(map), Some(asset_path)) => (map, asset_path),
+        _ => return,
+    };
+
+    // Maps imported from Tiled are saved next to the original in the `.map` format.
+    let path = asset_path.path().with_extension("map");
+    let file_path = FileAssetIo::get_base_path()
+        .join(&asset_server_settings.asset_folder)
+        .join(&path);
+    let status = match fs::write(&file_path, map.to_string()) {
+        Ok(()) => format!("SAVED {}", path.display()),
+        Err(err) => format!("COULD NOT SAVE {}: {}", path.display(), err),
+    };
+    for mut text in hud_query.iter_mut() {
+        text.sections[2].value = status.clone();
+    }
+}
+
+fn leave_editor(
+    mut commands: Commands,
+    mut state: ResMut<State<AppState>>,
+    mut keyboard_input: ResMut<Input<KeyCode>>,
+) {
+    if keyboard_input.clear_just_pressed(KeyCode::Tab) {
+        commands.insert_resource(PlayTest);
+        state.set(AppState::LoadingLevel).unwrap();
+    } else if keyboard_input.clear_just_pressed(KeyCode::Escape) {
+        state.set(AppState::MainMenu).unwrap();
+    }
+}
+
+fn return_to_editor(
+    mut state: ResMut<State<AppState>>,
+    mut keyboard_input: ResMut<Input<KeyCode>>,
+    play_test: Option<Res<PlayTest>>,
+) {
+    if play_test.is_some() && keyboard_input.clear_just_pressed(KeyCode::Tab) {
+        // Ignored if the level is ending on this same frame.
+        let _ = state.set(AppState::Editor);
+    }
+}
+
+fn end_play_test(mut commands: Commands) {
+    commands.remove_resource::<PlayTest>();
+}
+
+fn despawn_editor(mut commands: Commands, query: Query<Entity, With<EditorEntity>>) {
+    for entity in query.iter() {
+        commands.entity(entity).despawn_recursive();
+    }
+}
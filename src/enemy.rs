@@ -1,17 +1,23 @@
 use super::animation::{Animation, AnimationData, AnimationState};
 use super::app::AppState;
 use super::assets::GameAssets;
-use super::map::{Map, TILE_HEIGHT, TILE_WIDTH};
+use super::campaign::{CurrentLevel, LevelEntity};
+use super::health::{Deaths, Health, Invulnerable};
+use super::map::{Map, MapEntity, MapReloaded, TILE_HEIGHT, TILE_WIDTH};
 use super::player::{Player, PlayerAnimations};
+use super::tick::{self, TickStage};
 use benimator::Frame;
 use bevy::prelude::*;
 use bevy_rapier2d::prelude::*;
 use std::time::Duration;
 
 const ENEMY_VEL_MAGNITUDE: f32 = 30.;
-const ENEMY_ATTACK_RADIUS: f32 = 64.;
+pub const ENEMY_ATTACK_RADIUS: f32 = 64.;
 
-#[derive(PartialEq)]
+/// How fast a bite throws the player away from the piranha, in pixels per second.
+const KNOCKBACK_SPEED: f32 = 80.;
+
+#[derive(Debug, PartialEq)]
 enum EnemyState {
     Roaming = 0,
     Attacking = 1,
@@ -19,7 +25,7 @@ enum EnemyState {
 }
 
 #[derive(Component)]
-struct Enemy {
+pub struct Enemy {
     state: EnemyState,
 }
 
@@ -28,11 +34,14 @@ pub struct EnemyPlugin;
 impl Plugin for EnemyPlugin {
     fn build(&self, app: &mut App) {
         app.add_system_set(SystemSet::on_enter(AppState::Game).with_system(setup_enemies))
-            .add_system_set(
-                SystemSet::on_update(AppState::Game)
-                    .with_system(enemy_collision)
-                    .with_system(update_enemies),
-            );
+            .add_system_set(SystemSet::on_update(AppState::Game).with_system(reload_enemies));
+        tick::add_systems(
+            app,
+            TickStage::Gameplay,
+            SystemSet::new()
+                .with_system(enemy_collision)
+                .with_system(update_enemies),
+        );
     }
 }
 
@@ -41,6 +50,32 @@ fn setup_enemies(
     game_assets: Res<GameAssets>,
     mut animations: ResMut<Assets<AnimationData>>,
     maps: Res<Assets<Map>>,
+    level: Res<CurrentLevel>,
+) {
+    let map = maps.get(&level.map).unwrap();
+    spawn_enemies(&mut commands, map, &game_assets, &mut animations);
+}
+
+fn reload_enemies(
+    mut commands: Commands,
+    mut map_reloaded_events: EventReader<MapReloaded>,
+    game_assets: Res<GameAssets>,
+    mut animations: ResMut<Assets<AnimationData>>,
+    maps: Res<Assets<Map>>,
+    level: Res<CurrentLevel>,
+) {
+    if map_reloaded_events.iter().count() == 0 {
+        return;
+    }
+    let map = maps.get(&level.map).unwrap();
+    spawn_enemies(&mut commands, map, &game_assets, &mut animations);
+}
+
+fn spawn_enemies(
+    commands: &mut Commands,
+    map: &Map,
+    game_assets: &GameAssets,
+    animations: &mut Assets<AnimationData>,
 ) {
     let swim = AnimationData(benimator::Animation::from_frames(vec![
         Frame::new(0, Duration::from_millis(250)),
@@ -48,7 +83,6 @@ fn setup_enemies(
     ]));
     let swim_handle = animations.add(swim);
 
-    let map = maps.get(&game_assets.map).unwrap();
     for enemy in map.enemies.iter() {
         commands
             .spawn_bundle(SpriteSheetBundle {
@@ -79,26 +113,44 @@ fn setup_enemies(
             .insert(LockedAxes::ROTATION_LOCKED)
             .insert(Enemy {
                 state: EnemyState::Roaming,
-            });
+            })
+            .insert(LevelEntity)
+            .insert(MapEntity);
     }
 }
 
+/// The parts of the player a piranha can bite.
+type BittenPlayer = (
+    Entity,
+    &'static Transform,
+    &'static mut Velocity,
+    &'static mut Health,
+    &'static mut Animation,
+    &'static mut AnimationState,
+    &'static Visibility,
+    Option<&'static Invulnerable>,
+);
+
+#[allow(clippy::too_many_arguments)]
 fn enemy_collision(
     mut commands: Commands,
-    mut player_query: Query<
-        (Entity, &mut Animation, &mut AnimationState, &Visibility),
-        With<Player>,
-    >,
-    mut enemy_query: Query<(Entity, &mut Enemy, &mut Velocity, &mut TextureAtlasSprite)>,
+    mut deaths: Deaths,
+    mut player_query: Query<BittenPlayer, (With<Player>, Without<Enemy>)>,
+    mut enemy_query: Query<(
+        Entity,
+        &Transform,
+        &mut Enemy,
+        &mut Velocity,
+        &mut TextureAtlasSprite,
+    )>,
     mut collision_events: EventReader<CollisionEvent>,
+    rapier_context: Res<RapierContext>,
     game_assets: Res<GameAssets>,
     audio: Res<Audio>,
     player_animations: Res<PlayerAnimations>,
 ) {
-    let (player_entity, mut player_animation, mut player_animation_state, player_visibility) =
-        player_query.single_mut();
     for event in collision_events.iter() {
-        for (enemy_entity, mut enemy, mut velocity, mut sprite) in enemy_query.iter_mut() {
+        for (enemy_entity, _, enemy, mut velocity, mut sprite) in enemy_query.iter_mut() {
             if let CollisionEvent::Started(h1, h2, _flags) = event {
                 if enemy.state == EnemyState::Roaming
                     && (h1 == &enemy_entity || h2 == &enemy_entity)
@@ -106,53 +158,57 @@ fn enemy_collision(
                     velocity.linvel.x = -velocity.linvel.x;
                     sprite.flip_x = !sprite.flip_x;
                 }
-                if player_visibility.is_visible
-                    && enemy.state == EnemyState::Attacking
-                    && (h1 == &enemy_entity && h2 == &player_entity)
-                    || (h1 == &player_entity && h2 == &enemy_entity)
-                {
-                    commands.entity(player_entity).insert(GravityScale(0.));
-                    enemy.state = EnemyState::Eating;
-                    velocity.linvel = Vec2::splat(0.);
-                    audio.play(game_assets.crunch_sfx.clone());
-                    player_animation.0 = player_animations.dead.clone();
-                    player_animation_state.0.reset();
-
-                    let mut node = commands.spawn_bundle(NodeBundle {
-                        style: Style {
-                            size: Size::new(Val::Percent(100.0), Val::Percent(100.0)),
-                            position_type: PositionType::Absolute,
-                            justify_content: JustifyContent::Center,
-                            align_items: AlignItems::FlexEnd,
-                            ..default()
-                        },
-                        color: UiColor(Color::NONE),
-                        ..default()
-                    });
-                    node.add_children(|parent| {
-                        parent
-                            .spawn_bundle(
-                                TextBundle::from_section(
-                                    "YOU WERE\nDEVOURED.",
-                                    TextStyle {
-                                        font: game_assets.ui_font.clone(),
-                                        font_size: 200.0,
-                                        color: Color::WHITE,
-                                    },
-                                )
-                                .with_text_alignment(TextAlignment::CENTER)
-                                .with_style(Style {
-                                    align_self: AlignSelf::Center,
-                                    align_items: AlignItems::Center,
-                                    ..default()
-                                }),
-                            )
-                            .id()
-                    });
-                }
             }
         }
     }
+
+    let (
+        player_entity,
+        player_transform,
+        mut player_velocity,
+        mut health,
+        mut player_animation,
+        mut player_animation_state,
+        player_visibility,
+        invulnerable,
+    ) = player_query.single_mut();
+    if !player_visibility.is_visible
+        || invulnerable.is_some()
+        || player_animation.0 == player_animations.dead
+    {
+        return;
+    }
+
+    // Bites land for as long as a piranha is touching the player, not just when it first does.
+    for (enemy_entity, enemy_transform, mut enemy, mut velocity, _) in enemy_query.iter_mut() {
+        if enemy.state != EnemyState::Attacking
+            || rapier_context.intersection_pair(enemy_entity, player_entity) != Some(true)
+        {
+            continue;
+        }
+
+        audio.play(game_assets.crunch_sfx.clone());
+        health.0 = health.0.saturating_sub(1);
+        if health.0 > 0 {
+            let away = (player_transform.translation - enemy_transform.translation)
+                .truncate()
+                .try_normalize()
+                .unwrap_or(Vec2::Y);
+            player_velocity.linvel = away * KNOCKBACK_SPEED;
+            commands
+                .entity(player_entity)
+                .insert(Invulnerable::default());
+        } else if deaths.kill(
+            player_entity,
+            &mut player_animation,
+            &mut player_animation_state,
+            "YOU WERE\nDEVOURED.",
+        ) {
+            enemy.state = EnemyState::Eating;
+            velocity.linvel = Vec2::splat(0.);
+        }
+        return;
+    }
 }
 
 fn update_enemies(
@@ -208,3 +264,78 @@ fn update_enemies(
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::harness::Harness;
+    use crate::health::{Lives, MAX_HEALTH};
+    use crate::replay::TickInput;
+
+    const LEVEL: &str = "1 1 1 1 1 1 1 1
+                         1 0 0 0 0 0 0 1
+                         1 P 0 E 0 0 X 1
+                         1 1 1 1 1 1 1 1";
+
+    #[test]
+    fn attacking_piranha_bites_player_away() {
+        let mut harness = Harness::new(LEVEL);
+        harness.run_until(30, default(), |world| {
+            world.query::<&Enemy>().single(world).state == EnemyState::Attacking
+        });
+        harness.run_until(120, default(), |world| {
+            world
+                .query_filtered::<&Health, With<Player>>()
+                .single(world)
+                .0
+                < MAX_HEALTH
+        });
+
+        assert_eq!(harness.single::<&Health, With<Player>>().0, MAX_HEALTH - 1);
+        assert!(
+            harness.single::<&Velocity, With<Player>>().linvel.x < 0.,
+            "the player should be knocked away from the piranha"
+        );
+        harness.run(1, default());
+        assert!(harness
+            .single::<Option<&Invulnerable>, With<Player>>()
+            .is_some());
+        assert!(!Harness::player_is_dead(&mut harness.app.world));
+        assert_eq!(
+            harness.single::<&Enemy, ()>().state,
+            EnemyState::Attacking,
+            "the piranha should keep chasing"
+        );
+    }
+
+    #[test]
+    fn piranha_eats_player_on_last_life() {
+        let mut harness = Harness::new(LEVEL);
+        harness.app.world.insert_resource(Lives(1));
+        harness.single::<&mut Health, With<Player>>().0 = 1;
+        harness.run_until(150, default(), Harness::player_is_dead);
+
+        assert_eq!(
+            harness.single::<&Enemy, ()>().state,
+            EnemyState::Eating,
+            "the piranha should stop to eat"
+        );
+        harness.run_until(240, default(), |world| {
+            world.resource::<State<AppState>>().current() == &AppState::GameOver
+        });
+    }
+
+    #[test]
+    fn distant_piranha_keeps_roaming() {
+        let mut harness = Harness::new(
+            "1 1 1 1 1 1 1 1 1 1 1 1
+             1 0 0 0 0 0 0 0 0 0 X 1
+             1 P 0 0 0 0 0 0 0 0 E 1
+             1 1 1 1 1 1 1 1 1 1 1 1",
+        );
+        harness.run(120, TickInput::default());
+
+        assert_eq!(harness.single::<&Enemy, ()>().state, EnemyState::Roaming);
+        assert!(!Harness::player_is_dead(&mut harness.app.world));
+    }
+}
@@ -0,0 +1,351 @@
+use super::animation::{Animation, AnimationData, AnimationState};
+use super::app::AppState;
+use super::assets::GameAssets;
+use super::campaign::{CurrentLevel, LevelEntity};
+use super::map::{Map, MapEntity, MapReloaded, TILE_HEIGHT, TILE_WIDTH};
+use super::player::{Player, PlayerAnimations};
+use super::settings;
+use super::tick::{self, TickStage};
+use bevy::{prelude::*, render::view::Visibility};
+use serde::{Deserialize, Serialize};
+
+const GHOST_DIR: &str = "ghosts";
+
+/// How opaque the ghost is drawn.
+const GHOST_ALPHA: f32 = 0.35;
+
+/// Drawn behind the player, enemies and treasure but in front of the tiles.
+const GHOST_Z: f32 = 0.5;
+
+/// Which of the player's animations a ghost is showing.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum GhostAnimation {
+    Idle,
+    Walk,
+    Jump,
+    Swim,
+    Dead,
+}
+
+impl GhostAnimation {
+    fn of(animation: &Handle<AnimationData>, animations: &PlayerAnimations) -> Self {
+        if animation == &animations.walk {
+            GhostAnimation::Walk
+        } else if animation == &animations.jump {
+            GhostAnimation::Jump
+        } else if animation == &animations.swim {
+            GhostAnimation::Swim
+        } else if animation == &animations.dead {
+            GhostAnimation::Dead
+        } else {
+            GhostAnimation::Idle
+        }
+    }
+
+    fn handle(self, animations: &PlayerAnimations) -> &Handle<AnimationData> {
+        match self {
+            GhostAnimation::Idle => &animations.idle,
+            GhostAnimation::Walk => &animations.walk,
+            GhostAnimation::Jump => &animations.jump,
+            GhostAnimation::Swim => &animations.swim,
+            GhostAnimation::Dead => &animations.dead,
+        }
+    }
+}
+
+/// Where the player was and what they looked like on one tick.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+struct GhostFrame {
+    position: Vec2,
+    flip_x: bool,
+    animation: GhostAnimation,
+}
+
+/// A successful run through a level, one frame per tick.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+struct GhostRun {
+    frames: Vec<GhostFrame>,
+}
+
+impl GhostRun {
+    /// Where the best run of a version of a map is saved.
+    fn file(dir: &GhostDir, map_hash: u64) -> String {
+        format!("{}/{:016x}.json", dir.0, map_hash)
+    }
+}
+
+/// The directory the best runs are saved in, next to the game unless it is an absolute path.
+pub struct GhostDir(pub String);
+
+impl Default for GhostDir {
+    fn default() -> Self {
+        GhostDir(GHOST_DIR.to_string())
+    }
+}
+
+/// The fastest run of the level being played, and the run in progress to compare with it.
+#[derive(Default)]
+struct GhostRace {
+    map_hash: u64,
+    best: Option<GhostRun>,
+    /// `None` once the run can no longer count, such as after the map is edited.
+    current: Option<GhostRun>,
+    /// How many ticks of the best run have been played back.
+    tick: usize,
+}
+
+/// The best run of the level played back alongside the player.
+#[derive(Component)]
+struct Ghost;
+
+pub struct GhostPlugin;
+
+impl Plugin for GhostPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<GhostRace>()
+            .init_resource::<GhostDir>()
+            .add_system_set(SystemSet::on_enter(AppState::Game).with_system(start_race))
+            .add_system_set(SystemSet::on_update(AppState::Game).with_system(reload_race))
+            .add_system_set(SystemSet::on_enter(AppState::LevelComplete).with_system(finish_race));
+        tick::add_systems(
+            app,
+            TickStage::Gameplay,
+            SystemSet::new()
+                .with_system(record_ghost)
+                .with_system(play_ghost),
+        );
+    }
+}
+
+fn start_race(
+    mut commands: Commands,
+    mut race: ResMut<GhostRace>,
+    dir: Res<GhostDir>,
+    game_assets: Res<GameAssets>,
+    maps: Res<Assets<Map>>,
+    level: Res<CurrentLevel>,
+) {
+    let map = maps.get(&level.map).unwrap();
+    let best = settings::load::<Option<GhostRun>>(&GhostRun::file(&dir, map.file_hash));
+    if best.is_some() {
+        // Starts where the player does, and takes on their animations from the first tick.
+        commands
+            .spawn_bundle(SpriteSheetBundle {
+                texture_atlas: game_assets.player_atlas.clone(),
+                transform: Transform::from_translation(Vec3::new(
+                    TILE_WIDTH * map.player_spawn.0 as f32,
+                    TILE_HEIGHT * map.player_spawn.1 as f32,
+                    GHOST_Z,
+                )),
+                sprite: TextureAtlasSprite {
+                    index: 0,
+                    color: Color::rgba(1., 1., 1., GHOST_ALPHA),
+                    ..default()
+                },
+                ..default()
+            })
+            .insert(Ghost)
+            .insert(LevelEntity)
+            .insert(MapEntity);
+    }
+    *race = GhostRace {
+        map_hash: map.file_hash,
+        best,
+        current: Some(default()),
+        tick: 0,
+    };
+}
+
+/// An edited map is a different level, so the ghost of the old one is gone and this run no
+/// longer counts for either.
+fn reload_race(mut map_reloaded_events: EventReader<MapReloaded>, mut race: ResMut<GhostRace>) {
+    if map_reloaded_events.iter().count() == 0 {
+        return;
+    }
+    race.best = None;
+    race.current = None;
+}
+
+fn record_ghost(
+    mut race: ResMut<GhostRace>,
+    player_query: Query<(&Transform, &TextureAtlasSprite, &Animation), With<Player>>,
+    animations: Res<PlayerAnimations>,
+) {
+    let current = match race.current.as_mut() {
+        Some(current) => current,
+        None => return,
+    };
+    let (transform, sprite, animation) = player_query.single();
+    current.frames.push(GhostFrame {
+        position: transform.translation.truncate(),
+        flip_x: sprite.flip_x,
+        animation: GhostAnimation::of(animation, &animations),
+    });
+}
+
+/// The parts of the ghost that follow the best run.
+type GhostParts = (
+    Entity,
+    &'static mut Transform,
+    &'static mut TextureAtlasSprite,
+    &'static mut Visibility,
+    Option<&'static Animation>,
+);
+
+fn play_ghost(
+    mut commands: Commands,
+    mut race: ResMut<GhostRace>,
+    mut ghost_query: Query<GhostParts, With<Ghost>>,
+    animations: Res<PlayerAnimations>,
+) {
+    let race = &mut *race;
+    let best = match &race.best {
+        Some(best) => best,
+        None => return,
+    };
+    for (entity, mut transform, mut sprite, mut visibility, animation) in ghost_query.iter_mut() {
+        let frame = match best.frames.get(race.tick) {
+            Some(frame) => frame,
+            // The best run has gone through the portal.
+            None => {
+                visibility.is_visible = false;
+                continue;
+            }
+        };
+        transform.translation.x = frame.position.x;
+        transform.translation.y = frame.position.y;
+        sprite.flip_x = frame.flip_x;
+        let handle = frame.animation.handle(&animations);
+        if animation.map(|animation| &animation.0) != Some(handle) {
+            commands
+                .entity(entity)
+                .insert(Animation(handle.clone()))
+                .insert(AnimationState::default());
+        }
+    }
+    race.tick += 1;
+}
+
+/// Saves the run just completed if it beat the best one.
+fn finish_race(mut race: ResMut<GhostRace>, dir: Res<GhostDir>) {
+    let current = match race.current.take() {
+        Some(current) => current,
+        None => return,
+    };
+    let faster = match &race.best {
+        Some(best) => current.frames.len() < best.frames.len(),
+        None => true,
+    };
+    if faster {
+        settings::save(&GhostRun::file(&dir, race.map_hash), &current);
+        race.best = Some(current);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::harness::{Harness, SWIM_RIGHT};
+
+    const LEVEL: &str = "1 1 1 1 1
+                         1 0 0 0 1
+                         1 P 0 X 1
+                         1 1 1 1 1";
+
+    /// Waits for `idle_ticks`, then swims through the portal.
+    fn complete(harness: &mut Harness, idle_ticks: usize) {
+        harness.run(idle_ticks, default());
+        harness.run_until(600, SWIM_RIGHT, Harness::level_complete);
+    }
+
+    /// How many ticks the best run saved for the harness's map took.
+    fn saved_ticks(harness: &Harness) -> Option<usize> {
+        let world = &harness.app.world;
+        let file = GhostRun::file(world.resource(), world.resource::<GhostRace>().map_hash);
+        settings::load::<Option<GhostRun>>(&file).map(|run| run.frames.len())
+    }
+
+    fn ghosts(harness: &mut Harness) -> usize {
+        let world = &mut harness.app.world;
+        world
+            .query_filtered::<(), With<Ghost>>()
+            .iter(world)
+            .count()
+    }
+
+    #[test]
+    fn only_faster_runs_are_saved() {
+        let mut first = Harness::new(LEVEL);
+        complete(&mut first, 60);
+        let slow = saved_ticks(&first).unwrap();
+
+        let mut faster = Harness::with_ghost_dir(LEVEL, &first.ghost_dir());
+        complete(&mut faster, 0);
+        let fast = saved_ticks(&faster).unwrap();
+        assert!(fast < slow);
+
+        let mut slower = Harness::with_ghost_dir(LEVEL, &first.ghost_dir());
+        complete(&mut slower, 30);
+        assert_eq!(saved_ticks(&slower), Some(fast));
+    }
+
+    #[test]
+    fn ghost_follows_best_run_then_disappears() {
+        let mut first = Harness::new(LEVEL);
+        complete(&mut first, 0);
+
+        let mut harness = Harness::with_ghost_dir(LEVEL, &first.ghost_dir());
+        let best = harness
+            .app
+            .world
+            .resource::<GhostRace>()
+            .best
+            .clone()
+            .unwrap();
+        for frame in best.frames.iter() {
+            harness.run(1, default());
+            let (transform, sprite, visibility) =
+                harness.single::<(&Transform, &TextureAtlasSprite, &Visibility), With<Ghost>>();
+            assert_eq!(transform.translation.truncate(), frame.position);
+            assert_eq!(sprite.flip_x, frame.flip_x);
+            assert!(visibility.is_visible);
+        }
+
+        harness.run(1, default());
+        assert!(!harness.single::<&Visibility, With<Ghost>>().is_visible);
+    }
+
+    #[test]
+    fn edited_map_has_no_ghost() {
+        let mut first = Harness::new(LEVEL);
+        complete(&mut first, 0);
+
+        let edited = LEVEL.replacen("1 0 0 0 1", "1 A 0 0 1", 1);
+        let mut harness = Harness::with_ghost_dir(&edited, &first.ghost_dir());
+        assert!(harness.app.world.resource::<GhostRace>().best.is_none());
+        assert_eq!(ghosts(&mut harness), 0);
+    }
+
+    #[test]
+    fn reloading_map_ends_race() {
+        let mut first = Harness::new(LEVEL);
+        complete(&mut first, 0);
+        let mut harness = Harness::with_ghost_dir(LEVEL, &first.ghost_dir());
+        assert_eq!(ghosts(&mut harness), 1);
+
+        let map = harness.app.world.resource::<CurrentLevel>().map.clone();
+        harness
+            .app
+            .world
+            .resource_mut::<Assets<Map>>()
+            .get_mut(&map)
+            .unwrap();
+        harness.run(3, default());
+
+        let race = harness.app.world.resource::<GhostRace>();
+        assert!(race.best.is_none());
+        assert!(race.current.is_none());
+        assert_eq!(ghosts(&mut harness), 0);
+    }
+}
@@ -0,0 +1,205 @@
+//! A headless version of the game for testing gameplay: no window, rendering or audio, with
+//! time advanced by exactly one gameplay tick per frame.
+
+use super::accessibility::AccessibilitySettings;
+use super::animation::{Animation, AnimationPlugin};
+use super::app::AppState;
+use super::assets::GameAssets;
+use super::campaign::CurrentLevel;
+use super::checkpoint::CheckpointPlugin;
+use super::enemy::EnemyPlugin;
+use super::ghost::{GhostDir, GhostPlugin};
+use super::health::HealthPlugin;
+use super::input::InputBindings;
+use super::map::{Map, MapPlugin};
+use super::oxygen::OxygenPlugin;
+use super::player::{Player, PlayerAnimations, PlayerPlugin};
+use super::portal::PortalPlugin;
+use super::replay::{GameRng, TickInput};
+use super::restart::RestartPlugin;
+use super::tick::{TickPlugin, TICK};
+use super::tileset::Tileset;
+use super::treasure::TreasurePlugin;
+use bevy::{
+    asset::AssetPlugin,
+    ecs::query::{QueryItem, WorldQuery},
+    prelude::*,
+    time::{create_time_channels, TimeSender},
+};
+use bevy_rapier2d::prelude::*;
+use std::{
+    env, fs,
+    path::{Path, PathBuf},
+    sync::atomic::{AtomicUsize, Ordering},
+    time::{Duration, Instant},
+};
+
+/// Holding the stick all the way right, the usual way through a test level.
+pub const SWIM_RIGHT: TickInput = TickInput {
+    move_left: 0.,
+    move_right: 1.,
+    swim_up: false,
+};
+
+/// Tells apart the ghost directories of harnesses running at the same time.
+static NEXT_GHOST_DIR: AtomicUsize = AtomicUsize::new(0);
+
+/// A level being played without a window. Asset handles are left as stubs that point at nothing,
+/// since nothing is drawn or heard.
+pub struct Harness {
+    pub app: App,
+    time: TimeSender,
+    now: Instant,
+    /// A directory of ghosts made for this harness alone, removed along with it.
+    own_ghost_dir: Option<PathBuf>,
+}
+
+impl Harness {
+    /// Starts playing a map written in the `.map` format, panicking if it is not valid. Ghosts
+    /// are kept in a temporary directory of its own, so they never come from or go to the game.
+    pub fn new(map: &str) -> Self {
+        let dir = env::temp_dir().join(format!(
+            "tins2022-ghosts-{}-{}",
+            std::process::id(),
+            NEXT_GHOST_DIR.fetch_add(1, Ordering::Relaxed)
+        ));
+        let mut harness = Harness::with_ghost_dir(map, &dir);
+        harness.own_ghost_dir = Some(dir);
+        harness
+    }
+
+    /// Like `new`, but racing against ghosts saved in `ghost_dir`, such as by an earlier harness.
+    pub fn with_ghost_dir(map: &str, ghost_dir: &Path) -> Self {
+        let mut app = App::new();
+        app.add_plugins(MinimalPlugins)
+            .add_plugin(TransformPlugin)
+            .add_plugin(HierarchyPlugin)
+            .add_plugin(AssetPlugin)
+            .add_plugin(bevy::input::InputPlugin)
+            .add_asset::<Mesh>()
+            .init_resource::<Audio>()
+            .init_resource::<GameAssets>()
+            .init_resource::<InputBindings>()
+            .init_resource::<AccessibilitySettings>()
+            .init_resource::<TickInput>()
+            .init_resource::<GameRng>()
+            .insert_resource(GhostDir(ghost_dir.to_str().unwrap().to_string()))
+            .add_plugin(TickPlugin)
+            .add_plugin(MapPlugin)
+            .add_plugin(TreasurePlugin)
+            .add_plugin(PlayerPlugin)
+            .add_plugin(OxygenPlugin)
+            .add_plugin(HealthPlugin)
+            .add_plugin(EnemyPlugin)
+            .add_plugin(GhostPlugin)
+            .add_plugin(AnimationPlugin)
+            .add_plugin(PortalPlugin)
+            .add_plugin(CheckpointPlugin)
+            .add_plugin(RestartPlugin)
+            .add_plugin(
+                RapierPhysicsPlugin::<NoUserData>::default().with_default_system_setup(false),
+            );
+
+        // Time comes from here instead of the clock, just as it would from the render world.
+        let (time, time_receiver) = create_time_channels();
+        app.insert_resource(time_receiver);
+
+        let map = app
+            .world
+            .resource_mut::<Assets<Map>>()
+            .add(map.parse::<Map>().unwrap());
+        app.insert_resource(CurrentLevel { index: 0, map });
+        let tileset = app
+            .world
+            .resource_mut::<Assets<Tileset>>()
+            .add(Tileset::untiled(default()));
+        app.world.resource_mut::<GameAssets>().tileset = tileset;
+        app.add_state(AppState::Game);
+
+        let mut harness = Harness {
+            app,
+            time,
+            now: Instant::now(),
+            own_ghost_dir: None,
+        };
+        // The first frame spawns the level, and starts the clock without running a tick.
+        harness.update();
+        harness
+    }
+
+    fn update(&mut self) {
+        self.time.0.send(self.now).unwrap();
+        self.app.update();
+    }
+
+    /// Runs a frame for each of `ticks` ticks with the same input held throughout.
+    pub fn run(&mut self, ticks: usize, input: TickInput) {
+        // Rounded up, so every frame is long enough for a whole tick.
+        let frame = Duration::from_nanos((TICK as f64 * 1e9).ceil() as u64);
+        for _ in 0..ticks {
+            *self.app.world.resource_mut::<TickInput>() = input;
+            self.now += frame;
+            self.update();
+        }
+    }
+
+    /// Runs frames until `done` is true, failing if that takes more than `ticks` ticks.
+    pub fn run_until(
+        &mut self,
+        ticks: usize,
+        input: TickInput,
+        mut done: impl FnMut(&mut World) -> bool,
+    ) {
+        for _ in 0..ticks {
+            if done(&mut self.app.world) {
+                return;
+            }
+            self.run(1, input);
+        }
+        assert!(
+            done(&mut self.app.world),
+            "still waiting after {} ticks",
+            ticks
+        );
+    }
+
+    /// The only entity matching a query, such as the player's components.
+    pub fn single<Q: WorldQuery, F: WorldQuery>(&mut self) -> QueryItem<'_, Q> {
+        self.app
+            .world
+            .query_filtered::<Q, F>()
+            .single_mut(&mut self.app.world)
+    }
+
+    /// Whether the player is playing their death animation, for passing to `run_until`.
+    pub fn player_is_dead(world: &mut World) -> bool {
+        let dead = world.resource::<PlayerAnimations>().dead.clone();
+        world
+            .query_filtered::<&Animation, With<Player>>()
+            .single(world)
+            .0
+            == dead
+    }
+
+    /// Whether the player has gone through the portal, for passing to `run_until`.
+    pub fn level_complete(world: &mut World) -> bool {
+        world.resource::<State<AppState>>().current() == &AppState::LevelComplete
+    }
+
+    /// Where this harness saves and loads ghosts.
+    pub fn ghost_dir(&self) -> PathBuf {
+        PathBuf::from(&self.app.world.resource::<GhostDir>().0)
+    }
+
+    pub fn state(&self) -> &AppState {
+        self.app.world.resource::<State<AppState>>().current()
+    }
+}
+
+impl Drop for Harness {
+    fn drop(&mut self) {
+        if let Some(dir) = &self.own_ghost_dir {
+            let _ = fs::remove_dir_all(dir);
+        }
+    }
+}
@@ -0,0 +1,348 @@
+use super::animation::{Animation, AnimationState};
+use super::app::AppState;
+use super::assets::GameAssets;
+use super::campaign::{CurrentLevel, LevelEntity};
+use super::map::{Map, MapReloaded, TILE_HEIGHT, TILE_WIDTH};
+use super::oxygen::Oxygen;
+use super::player::{Player, PlayerAnimations};
+use super::restart::LevelOver;
+use super::tick::{self, TickStage, TICK};
+use super::treasure::{self, CollectedTreasure, Treasure};
+use bevy::{ecs::system::SystemParam, prelude::*};
+use bevy_rapier2d::prelude::*;
+
+/// How many bites the player can take before losing a life.
+pub const MAX_HEALTH: u32 = 3;
+
+/// How many times the player can die in a level before it is over.
+const STARTING_LIVES: u32 = 3;
+
+/// How long the player can't be bitten after being bitten or coming back, in seconds.
+const INVULNERABLE_TIME: f32 = 2.;
+
+/// How opaque the player is drawn while they can't be bitten. They are shown faded rather than
+/// blinking, so nothing flashes.
+const INVULNERABLE_ALPHA: f32 = 0.5;
+
+/// How many bites the player can still take before losing a life.
+#[derive(Component)]
+pub struct Health(pub u32);
+
+impl Default for Health {
+    fn default() -> Self {
+        Health(MAX_HEALTH)
+    }
+}
+
+/// Keeps the player from being bitten for the ticks it holds.
+#[derive(Component)]
+pub struct Invulnerable(pub u32);
+
+impl Default for Invulnerable {
+    fn default() -> Self {
+        Invulnerable((INVULNERABLE_TIME / TICK).round() as u32)
+    }
+}
+
+/// How many lives the player has left in this attempt at the level, including the current one.
+pub struct Lives(pub u32);
+
+impl Default for Lives {
+    fn default() -> Self {
+        Lives(STARTING_LIVES)
+    }
+}
+
+/// Where the player comes back to after losing a life, and the treasure that was still left when
+/// they last got there. Any of it they have collected since is put back when they respawn.
+pub struct RespawnPoint {
+    pub cell: (u32, u32),
+    pub treasures: Vec<(u32, u32)>,
+}
+
+impl RespawnPoint {
+    fn from_map(map: &Map) -> Self {
+        RespawnPoint {
+            cell: map.player_spawn,
+            treasures: map.treasures.clone(),
+        }
+    }
+}
+
+/// Whether the player has already lost a life this tick, so that being bitten and drowning at once
+/// only costs one.
+#[derive(Default)]
+struct DiedThisTick(bool);
+
+/// Marks the text showing the player's health and lives.
+#[derive(Component)]
+struct HealthDisplay;
+
+/// Takes the player's lives, for systems that can kill them.
+#[derive(SystemParam)]
+pub struct Deaths<'w, 's> {
+    commands: Commands<'w, 's>,
+    lives: ResMut<'w, Lives>,
+    died_this_tick: ResMut<'w, DiedThisTick>,
+    respawn_point: Res<'w, RespawnPoint>,
+    treasure_query: Query<'w, 's, &'static Treasure>,
+    collected: ResMut<'w, CollectedTreasure>,
+    animations: Res<'w, PlayerAnimations>,
+    game_assets: Res<'w, GameAssets>,
+    time: Res<'w, Time>,
+}
+
+impl<'w, 's> Deaths<'w, 's> {
+    /// Takes one of the player's lives. They come back at the respawn point if they have any
+    /// left, otherwise the level ends showing how they died. Returns whether the level is over,
+    /// and does nothing if they already lost a life this tick.
+    pub fn kill(
+        &mut self,
+        player: Entity,
+        animation: &mut Animation,
+        animation_state: &mut AnimationState,
+        message: &str,
+    ) -> bool {
+        if self.died_this_tick.0 {
+            return false;
+        }
+        self.died_this_tick.0 = true;
+        self.lives.0 = self.lives.0.saturating_sub(1);
+        if self.lives.0 > 0 {
+            self.respawn(player);
+            return false;
+        }
+
+        self.commands.entity(player).insert(GravityScale(0.));
+        animation.0 = self.animations.dead.clone();
+        animation_state.0.reset();
+
+        let mut node = self.commands.spawn_bundle(NodeBundle {
+            style: Style {
+                size: Size::new(Val::Percent(100.0), Val::Percent(100.0)),
+                position_type: PositionType::Absolute,
+                justify_content: JustifyContent::Center,
+                align_items: AlignItems::FlexEnd,
+                ..default()
+            },
+            color: UiColor(Color::NONE),
+            ..default()
+        });
+        let font = self.game_assets.ui_font.clone();
+        node.add_children(|parent| {
+            parent
+                .spawn_bundle(
+                    TextBundle::from_section(
+                        message,
+                        TextStyle {
+                            font,
+                            font_size: 200.0,
+                            color: Color::WHITE,
+                        },
+                    )
+                    .with_text_alignment(TextAlignment::CENTER)
+                    .with_style(Style {
+                        align_self: AlignSelf::Center,
+                        align_items: AlignItems::Center,
+                        ..default()
+                    }),
+                )
+                .id()
+        });
+        node.insert(LevelOver {
+            since: self.time.time_since_startup(),
+        })
+        .insert(LevelEntity);
+        true
+    }
+
+    fn respawn(&mut self, player: Entity) {
+        let transform = Transform::from_translation(Vec3::new(
+            TILE_WIDTH * self.respawn_point.cell.0 as f32,
+            TILE_HEIGHT * self.respawn_point.cell.1 as f32,
+            1.,
+        ));
+        // Rapier only notices a body has been moved from its global transform, which would not
+        // be updated until after this tick's physics step.
+        self.commands
+            .entity(player)
+            .insert(transform)
+            .insert(GlobalTransform::from(transform))
+            .insert(Velocity::zero())
+            .insert(Health::default())
+            .insert(Oxygen::default())
+            .insert(Invulnerable::default());
+
+        for &cell in self.respawn_point.treasures.iter() {
+            if !self
+                .treasure_query
+                .iter()
+                .any(|treasure| treasure.cell == cell)
+            {
+                treasure::spawn_treasure_at(&mut self.commands, cell, &self.game_assets);
+            }
+        }
+        let treasures = &self.respawn_point.treasures;
+        self.collected.0.retain(|cell| !treasures.contains(cell));
+    }
+}
+
+pub struct HealthPlugin;
+
+impl Plugin for HealthPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<Lives>()
+            .init_resource::<DiedThisTick>()
+            .insert_resource(RespawnPoint {
+                cell: (0, 0),
+                treasures: vec![],
+            })
+            .add_system_set(
+                SystemSet::on_enter(AppState::Game)
+                    .with_system(setup_lives)
+                    .with_system(setup_health_display),
+            )
+            .add_system_set(
+                SystemSet::on_update(AppState::Game)
+                    .with_system(reload_respawn_point)
+                    .with_system(update_health_display),
+            );
+        tick::add_systems(
+            app,
+            TickStage::Input,
+            SystemSet::new().with_system(forget_death),
+        );
+        tick::add_systems(
+            app,
+            TickStage::Gameplay,
+            SystemSet::new().with_system(wear_off_invulnerability),
+        );
+    }
+}
+
+fn setup_lives(
+    mut lives: ResMut<Lives>,
+    mut respawn_point: ResMut<RespawnPoint>,
+    maps: Res<Assets<Map>>,
+    level: Res<CurrentLevel>,
+) {
+    let map = maps.get(&level.map).unwrap();
+    *lives = Lives::default();
+    *respawn_point = RespawnPoint::from_map(map);
+}
+
+/// Respawning at the player spawn is always safe, wherever it has moved to, and checkpoints may
+/// have been moved or removed.
+fn reload_respawn_point(
+    mut map_reloaded_events: EventReader<MapReloaded>,
+    mut respawn_point: ResMut<RespawnPoint>,
+    maps: Res<Assets<Map>>,
+    level: Res<CurrentLevel>,
+) {
+    if map_reloaded_events.iter().count() == 0 {
+        return;
+    }
+    let map = maps.get(&level.map).unwrap();
+    *respawn_point = RespawnPoint::from_map(map);
+}
+
+fn setup_health_display(mut commands: Commands, game_assets: Res<GameAssets>) {
+    commands
+        .spawn_bundle(
+            TextBundle::from_section(
+                "",
+                TextStyle {
+                    font: game_assets.ui_font.clone(),
+                    font_size: 40.0,
+                    color: Color::WHITE,
+                },
+            )
+            .with_style(Style {
+                position_type: PositionType::Absolute,
+                position: UiRect {
+                    top: Val::Px(20.0),
+                    right: Val::Px(20.0),
+                    ..default()
+                },
+                ..default()
+            }),
+        )
+        .insert(HealthDisplay)
+        .insert(LevelEntity);
+}
+
+fn update_health_display(
+    health_query: Query<&Health, With<Player>>,
+    lives: Res<Lives>,
+    mut display_query: Query<&mut Text, With<HealthDisplay>>,
+) {
+    let health = match health_query.get_single() {
+        Ok(health) => health,
+        Err(_) => return,
+    };
+    for mut text in display_query.iter_mut() {
+        text.sections[0].value = format!("HEALTH {}/{}  LIVES {}", health.0, MAX_HEALTH, lives.0);
+    }
+}
+
+fn forget_death(mut died_this_tick: ResMut<DiedThisTick>) {
+    died_this_tick.0 = false;
+}
+
+fn wear_off_invulnerability(
+    mut commands: Commands,
+    mut player_query: Query<(Entity, &mut Invulnerable, &mut TextureAtlasSprite), With<Player>>,
+) {
+    for (entity, mut invulnerable, mut sprite) in player_query.iter_mut() {
+        if invulnerable.0 == 0 {
+            commands.entity(entity).remove::<Invulnerable>();
+            sprite.color.set_a(1.);
+        } else {
+            invulnerable.0 -= 1;
+            sprite.color.set_a(INVULNERABLE_ALPHA);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::harness::Harness;
+
+    const LEVEL: &str = "1 1 1 1 1 1
+                         1 0 0 0 0 1
+                         1 P 0 0 X 1
+                         1 1 1 1 1 1";
+
+    /// Kills the player twice in its first tick, as if bitten and drowned at once.
+    fn kill_twice(
+        mut deaths: Deaths,
+        mut player_query: Query<(Entity, &mut Animation, &mut AnimationState), With<Player>>,
+        mut done: Local<bool>,
+    ) {
+        if *done {
+            return;
+        }
+        *done = true;
+        let (player, mut animation, mut animation_state) = player_query.single_mut();
+        for message in ["BITTEN", "DROWNED"] {
+            deaths.kill(player, &mut animation, &mut animation_state, message);
+        }
+    }
+
+    #[test]
+    fn dying_twice_in_a_tick_costs_one_life() {
+        let mut harness = Harness::new(LEVEL);
+        tick::add_systems(
+            &mut harness.app,
+            TickStage::Gameplay,
+            SystemSet::new().with_system(kill_twice),
+        );
+        harness.run(2, default());
+        assert_eq!(
+            harness.app.world.resource::<Lives>().0,
+            Lives::default().0 - 1
+        );
+        assert!(!Harness::player_is_dead(&mut harness.app.world));
+    }
+}
@@ -0,0 +1,324 @@
+use super::settings;
+use bevy::{ecs::system::SystemParam, prelude::*};
+use serde::{Deserialize, Serialize};
+use std::{collections::BTreeMap, marker::PhantomData};
+
+const BINDINGS_FILE: &str = "input.json";
+
+/// Something the player can do, whichever keys or buttons it is bound to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Action {
+    MoveLeft,
+    MoveRight,
+    SwimUp,
+    Pause,
+    Restart,
+}
+
+impl Action {
+    pub const ALL: [Action; 5] = [
+        Action::MoveLeft,
+        Action::MoveRight,
+        Action::SwimUp,
+        Action::Pause,
+        Action::Restart,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Action::MoveLeft => "MOVE LEFT",
+            Action::MoveRight => "MOVE RIGHT",
+            Action::SwimUp => "SWIM UP",
+            Action::Pause => "PAUSE",
+            Action::Restart => "RESTART",
+        }
+    }
+}
+
+/// The keys and gamepad buttons that trigger an action.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Binding {
+    pub keys: Vec<KeyCode>,
+    pub buttons: Vec<GamepadButtonType>,
+    /// A gamepad axis that triggers the action as far as it is pushed, and which way it has to be
+    /// pushed: 1 for positive or -1 for negative.
+    pub axis: Option<(GamepadAxisType, f32)>,
+}
+
+impl Binding {
+    /// The keys and buttons bound, for showing in menus.
+    pub fn describe(&self) -> String {
+        let inputs = self
+            .keys
+            .iter()
+            .map(|key| format!("{:?}", key))
+            .chain(self.buttons.iter().map(|button| format!("{:?}", button)))
+            .collect::<Vec<String>>();
+        if inputs.is_empty() {
+            "NONE".to_string()
+        } else {
+            inputs.join(" / ").to_uppercase()
+        }
+    }
+}
+
+/// Which keys and buttons trigger each action, saved whenever they change.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct InputBindings(BTreeMap<Action, Binding>);
+
+impl Default for InputBindings {
+    fn default() -> Self {
+        let binding = |keys: &[KeyCode], buttons: &[GamepadButtonType], axis| Binding {
+            keys: keys.to_vec(),
+            buttons: buttons.to_vec(),
+            axis,
+        };
+        InputBindings(BTreeMap::from([
+            (
+                Action::MoveLeft,
+                binding(
+                    &[KeyCode::Left],
+                    &[GamepadButtonType::DPadLeft],
+                    Some((GamepadAxisType::LeftStickX, -1.)),
+                ),
+            ),
+            (
+                Action::MoveRight,
+                binding(
+                    &[KeyCode::Right],
+                    &[GamepadButtonType::DPadRight],
+                    Some((GamepadAxisType::LeftStickX, 1.)),
+                ),
+            ),
+            (
+                Action::SwimUp,
+                binding(&[KeyCode::Space], &[GamepadButtonType::South], None),
+            ),
+            (
+                Action::Pause,
+                binding(&[KeyCode::Escape], &[GamepadButtonType::Start], None),
+            ),
+            (Action::Restart, binding(&[KeyCode::R], &[], None)),
+        ]))
+    }
+}
+
+impl InputBindings {
+    pub fn get(&self, action: Action) -> &Binding {
+        &self.0[&action]
+    }
+
+    pub fn get_mut(&mut self, action: Action) -> &mut Binding {
+        self.0.get_mut(&action).unwrap()
+    }
+
+    fn load() -> Self {
+        let mut bindings = settings::load::<InputBindings>(BINDINGS_FILE);
+        // Actions added since the file was saved keep their default bindings.
+        for (action, binding) in InputBindings::default().0 {
+            bindings.0.entry(action).or_insert(binding);
+        }
+        bindings
+    }
+}
+
+/// The state of every action, from the keyboard and all connected gamepads.
+#[derive(SystemParam)]
+pub struct Actions<'w, 's> {
+    bindings: Res<'w, InputBindings>,
+    keys: ResMut<'w, Input<KeyCode>>,
+    gamepads: Res<'w, Gamepads>,
+    buttons: ResMut<'w, Input<GamepadButton>>,
+    axes: Res<'w, Axis<GamepadAxis>>,
+    #[system_param(ignore)]
+    marker: PhantomData<&'s ()>,
+}
+
+impl<'w, 's> Actions<'w, 's> {
+    /// The buttons bound to an action on every connected gamepad.
+    fn gamepad_buttons(&self, binding: &Binding) -> Vec<GamepadButton> {
+        self.gamepads
+            .iter()
+            .flat_map(|gamepad| {
+                binding
+                    .buttons
+                    .iter()
+                    .map(|button_type| GamepadButton::new(*gamepad, *button_type))
+            })
+            .collect()
+    }
+
+    /// Whether a key or button bound to the action is held.
+    pub fn pressed(&self, action: Action) -> bool {
+        let binding = self.bindings.get(action);
+        self.keys.any_pressed(binding.keys.iter().copied())
+            || self.buttons.any_pressed(self.gamepad_buttons(binding))
+    }
+
+    /// Whether a key or button bound to the action was pressed this frame.
+    pub fn just_pressed(&self, action: Action) -> bool {
+        let binding = self.bindings.get(action);
+        self.keys.any_just_pressed(binding.keys.iter().copied())
+            || self.buttons.any_just_pressed(self.gamepad_buttons(binding))
+    }
+
+    /// Like `just_pressed`, but also clears the press so no other system acts on it too.
+    pub fn clear_just_pressed(&mut self, action: Action) -> bool {
+        let buttons = self.gamepad_buttons(self.bindings.get(action));
+        let mut just_pressed = false;
+        for key in self.bindings.get(action).keys.iter() {
+            just_pressed |= self.keys.clear_just_pressed(*key);
+        }
+        for button in buttons {
+            just_pressed |= self.buttons.clear_just_pressed(button);
+        }
+        just_pressed
+    }
+
+    /// How strongly the action is triggered, from 0 to 1. Keys and buttons trigger it fully, while
+    /// an axis triggers it as far as it is pushed; the furthest pushed gamepad wins.
+    pub fn value(&self, action: Action) -> f32 {
+        if self.pressed(action) {
+            return 1.;
+        }
+        let (axis_type, direction) = match self.bindings.get(action).axis {
+            Some(axis) => axis,
+            None => return 0.,
+        };
+        self.gamepads
+            .iter()
+            .filter_map(|gamepad| self.axes.get(GamepadAxis::new(*gamepad, axis_type)))
+            .map(|value| (value * direction).clamp(0., 1.))
+            .fold(0., f32::max)
+    }
+}
+
+pub struct InputPlugin;
+
+impl Plugin for InputPlugin {
+    fn build(&self, app: &mut App) {
+        app.insert_resource(InputBindings::load())
+            .add_system(save_bindings);
+    }
+}
+
+fn save_bindings(bindings: Res<InputBindings>) {
+    if bindings.is_changed() && !bindings.is_added() {
+        settings::save(BINDINGS_FILE, &*bindings);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::replay::TickInput;
+    use bevy::input::gamepad::{GamepadEventRaw, GamepadEventType};
+
+    const GAMEPAD: Gamepad = Gamepad { id: 0 };
+
+    fn read_tick_input(actions: Actions, mut input: ResMut<TickInput>) {
+        *input = TickInput::read(&actions);
+    }
+
+    /// An app reading the input for a tick from a connected gamepad every frame.
+    fn app() -> App {
+        let mut app = App::new();
+        app.add_plugin(bevy::input::InputPlugin)
+            .init_resource::<InputBindings>()
+            .init_resource::<TickInput>()
+            .add_system(read_tick_input);
+        send(&mut app, GamepadEventType::Connected);
+        app
+    }
+
+    /// Sends an event as if it came from the gamepad itself, and runs a frame.
+    fn send(app: &mut App, event: GamepadEventType) {
+        app.world
+            .resource_mut::<Events<GamepadEventRaw>>()
+            .send(GamepadEventRaw::new(GAMEPAD, event));
+        app.update();
+    }
+
+    fn stick(app: &App) -> Option<f32> {
+        app.world
+            .resource::<Axis<GamepadAxis>>()
+            .get(GamepadAxis::new(GAMEPAD, GamepadAxisType::LeftStickX))
+    }
+
+    #[test]
+    fn stick_swims_as_far_as_it_is_pushed() {
+        let mut app = app();
+        send(
+            &mut app,
+            GamepadEventType::AxisChanged(GamepadAxisType::LeftStickX, 0.5),
+        );
+        assert_eq!(stick(&app), Some(0.5));
+        assert_eq!(
+            *app.world.resource::<TickInput>(),
+            TickInput {
+                move_right: 0.5,
+                ..default()
+            }
+        );
+
+        send(
+            &mut app,
+            GamepadEventType::AxisChanged(GamepadAxisType::LeftStickX, -1.),
+        );
+        assert_eq!(
+            *app.world.resource::<TickInput>(),
+            TickInput {
+                move_left: 1.,
+                ..default()
+            }
+        );
+    }
+
+    #[test]
+    fn stick_resting_in_dead_zone_does_not_swim() {
+        let mut app = app();
+        send(
+            &mut app,
+            GamepadEventType::AxisChanged(GamepadAxisType::LeftStickX, 0.5),
+        );
+        send(
+            &mut app,
+            GamepadEventType::AxisChanged(GamepadAxisType::LeftStickX, 0.03),
+        );
+        assert_eq!(stick(&app), Some(0.));
+        assert_eq!(*app.world.resource::<TickInput>(), TickInput::default());
+    }
+
+    #[test]
+    fn buttons_trigger_their_actions() {
+        let mut app = app();
+        send(
+            &mut app,
+            GamepadEventType::ButtonChanged(GamepadButtonType::South, 1.),
+        );
+        assert!(app
+            .world
+            .resource::<Input<GamepadButton>>()
+            .pressed(GamepadButton::new(GAMEPAD, GamepadButtonType::South)));
+        send(
+            &mut app,
+            GamepadEventType::ButtonChanged(GamepadButtonType::DPadLeft, 1.),
+        );
+        assert_eq!(
+            *app.world.resource::<TickInput>(),
+            TickInput {
+                move_left: 1.,
+                swim_up: true,
+                ..default()
+            }
+        );
+
+        send(
+            &mut app,
+            GamepadEventType::ButtonChanged(GamepadButtonType::South, 0.),
+        );
+        assert!(!app.world.resource::<TickInput>().swim_up);
+    }
+}
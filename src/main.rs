@@ -1,33 +1,68 @@
 #![windows_subsystem = "windows"]
 
+mod accessibility;
 mod animation;
 mod app;
 mod assets;
 mod camera;
+mod campaign;
+mod checkpoint;
+mod display;
+mod editor;
 mod enemy;
+mod ghost;
+#[cfg(test)]
+mod harness;
+mod health;
+mod input;
 mod map;
+mod menu;
+mod oxygen;
 mod player;
 mod portal;
+mod post_processing;
+mod replay;
+mod restart;
+mod settings;
+mod tick;
+mod tiled;
+mod tileset;
 mod treasure;
 
+use accessibility::AccessibilityPlugin;
 use animation::AnimationPlugin;
 use app::AppState;
 use assets::GameAssets;
-use bevy::{prelude::*, render::texture::ImageSettings, window::WindowMode};
+use bevy::{
+    asset::AssetServerSettings, prelude::*, render::texture::ImageSettings, window::WindowMode,
+};
 use bevy_asset_loader::prelude::*;
 use bevy_rapier2d::prelude::*;
 use camera::CameraPlugin;
+use campaign::CampaignPlugin;
+use checkpoint::CheckpointPlugin;
+use display::DisplayPlugin;
+use editor::EditorPlugin;
 use enemy::EnemyPlugin;
+use ghost::GhostPlugin;
+use health::HealthPlugin;
+use input::InputPlugin;
 use map::MapPlugin;
+use menu::MenuPlugin;
+use oxygen::OxygenPlugin;
 use player::PlayerPlugin;
 use portal::PortalPlugin;
+use post_processing::PostProcessingPlugin;
+use replay::ReplayPlugin;
+use restart::RestartPlugin;
+use tick::TickPlugin;
 use treasure::TreasurePlugin;
 
 fn main() {
     App::new()
         .add_loading_state(
             LoadingState::new(AppState::Loading)
-                .continue_to_state(AppState::Game)
+                .continue_to_state(AppState::MainMenu)
                 .with_collection::<GameAssets>(),
         )
         .add_state(AppState::Loading)
@@ -39,15 +74,32 @@ fn main() {
             ..Default::default()
         })
         .insert_resource(ImageSettings::default_nearest()) // prevents blurry sprites
+        .insert_resource(AssetServerSettings {
+            watch_for_changes: true, // reloads maps when they are edited
+            ..default()
+        })
         .add_plugins(DefaultPlugins)
+        .add_plugin(AccessibilityPlugin)
+        .add_plugin(InputPlugin)
+        .add_plugin(TickPlugin)
+        .add_plugin(ReplayPlugin)
         .add_plugin(CameraPlugin)
+        .add_plugin(DisplayPlugin)
+        .add_plugin(PostProcessingPlugin)
+        .add_plugin(CampaignPlugin)
         .add_plugin(MapPlugin)
         .add_plugin(TreasurePlugin)
         .add_plugin(PlayerPlugin)
+        .add_plugin(OxygenPlugin)
+        .add_plugin(HealthPlugin)
         .add_plugin(EnemyPlugin)
+        .add_plugin(GhostPlugin)
         .add_plugin(AnimationPlugin)
         .add_plugin(PortalPlugin)
-        .add_plugin(RapierPhysicsPlugin::<NoUserData>::default())
-        .add_system(bevy::window::close_on_esc)
+        .add_plugin(CheckpointPlugin)
+        .add_plugin(RestartPlugin)
+        .add_plugin(MenuPlugin)
+        .add_plugin(EditorPlugin)
+        .add_plugin(RapierPhysicsPlugin::<NoUserData>::default().with_default_system_setup(false))
         .run();
 }
@@ -1,5 +1,8 @@
 use super::app::AppState;
 use super::assets::GameAssets;
+use super::campaign::{CurrentLevel, LevelEntity};
+use super::tiled::TiledMapLoader;
+use super::tileset::{Tileset, TilesetLoader};
 use bevy::{
     asset::{AssetLoader, LoadContext, LoadedAsset},
     prelude::*,
@@ -7,10 +10,13 @@ use bevy::{
     utils::BoxedFuture,
 };
 use bevy_rapier2d::prelude::*;
-use std::str;
+use std::{
+    fmt,
+    str::{self, FromStr},
+    sync::{Arc, Mutex},
+};
+use thiserror::Error;
 
-pub const MAP_WIDTH: usize = 36;
-pub const MAP_HEIGHT: usize = 20;
 pub const TILE_WIDTH: f32 = 16.;
 pub const TILE_HEIGHT: f32 = 16.;
 
@@ -18,20 +24,439 @@ pub const TILE_HEIGHT: f32 = 16.;
 pub enum Tile {
     Empty,
     Wall,
+    /// Water with air to breathe, which refills the player's oxygen.
+    Air,
+}
+
+/// Lightens the water of air pockets so they stand out from the rest.
+const AIR_POCKET_COLOR: Color = Color::rgb(1.6, 1.8, 2.);
+
+/// The color a tile's sprite is drawn with.
+pub fn tile_color(tile: Tile) -> Color {
+    match tile {
+        Tile::Air => AIR_POCKET_COLOR,
+        Tile::Empty | Tile::Wall => Color::WHITE,
+    }
+}
+
+/// A rectangle of wall tiles, measured in tiles from the bottom left of the map.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct WallRect {
+    pub column: usize,
+    pub row: usize,
+    pub width: usize,
+    pub height: usize,
+}
+
+/// How the water post processing pass looks in a level. Set in a `.map` file with a line such as
+/// `water distortion=0.03 tint=0,80,200,255`, or in Tiled with map properties such as
+/// `water.distortion`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct WaterSettings {
+    /// The size of the ripples; smaller values make larger ripples.
+    pub scale: f32,
+    /// How far the ripples move the picture, as a fraction of the screen.
+    pub distortion: f32,
+    /// How quickly each of the two layers of ripples drift across the screen.
+    pub motion: [Vec2; 2],
+    /// The color of the water, with components from 0 to 255.
+    pub tint: Vec4,
+    /// How much of the picture is replaced by the tint.
+    pub tint_amount: f32,
+    /// How much more saturated the picture is made, to make up for the tint.
+    pub saturation: f32,
+}
+
+impl Default for WaterSettings {
+    fn default() -> Self {
+        WaterSettings {
+            scale: 0.009,
+            distortion: 1. / 60.,
+            motion: [Vec2::new(0.3, -0.4), Vec2::new(0.1, 0.5)],
+            tint: Vec4::new(0., 100., 255., 255.),
+            tint_amount: 0.0025,
+            saturation: 1.4,
+        }
+    }
+}
+
+impl WaterSettings {
+    /// Sets one setting from its name and value, returning `None` if either is not valid.
+    pub fn set(&mut self, name: &str, value: &str) -> Option<()> {
+        let numbers = value
+            .split(',')
+            .map(|number| number.trim().parse::<f32>().ok())
+            .collect::<Option<Vec<f32>>>()?;
+        match (name, numbers.as_slice()) {
+            ("scale", [scale]) => self.scale = *scale,
+            ("distortion", [distortion]) => self.distortion = *distortion,
+            ("motion1", [x, y]) => self.motion[0] = Vec2::new(*x, *y),
+            ("motion2", [x, y]) => self.motion[1] = Vec2::new(*x, *y),
+            ("tint", [r, g, b, a]) => self.tint = Vec4::new(*r, *g, *b, *a),
+            ("tint_amount", [tint_amount]) => self.tint_amount = *tint_amount,
+            ("saturation", [saturation]) => self.saturation = *saturation,
+            _ => return None,
+        }
+        Some(())
+    }
+}
+
+/// Writes the settings as a `water` line of the `.map` format.
+impl fmt::Display for WaterSettings {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "water scale={} distortion={} motion1={},{} motion2={},{} tint={},{},{},{} \
+             tint_amount={} saturation={}",
+            self.scale,
+            self.distortion,
+            self.motion[0].x,
+            self.motion[0].y,
+            self.motion[1].x,
+            self.motion[1].y,
+            self.tint.x,
+            self.tint.y,
+            self.tint.z,
+            self.tint.w,
+            self.tint_amount,
+            self.saturation
+        )
+    }
 }
 
 #[derive(Debug, TypeUuid)]
 #[uuid = "e44e9629-7b52-41aa-94de-0a3bc1146b1e"]
 pub struct Map {
-    pub tiles: [[Tile; MAP_WIDTH]; MAP_HEIGHT],
+    pub width: usize,
+    pub height: usize,
+    /// Tiles indexed by row and then column, with row 0 at the bottom of the map.
+    pub tiles: Vec<Vec<Tile>>,
     pub player_spawn: (u32, u32),
     pub portal_spawn: (u32, u32),
     pub treasures: Vec<(u32, u32)>,
     pub enemies: Vec<(u32, u32)>,
+    pub checkpoints: Vec<(u32, u32)>,
+    pub water: WaterSettings,
+    /// Hash of the file the map was loaded from, from `file_hash`.
+    pub file_hash: u64,
+}
+
+/// Reasons a map can be rejected. Lines and columns are 1-based positions in a `.map` file, and
+/// objects are identified by their id in a Tiled map.
+#[derive(Debug, Error)]
+pub enum MapError {
+    #[error("map is not valid UTF-8: {0}")]
+    InvalidUtf8(#[from] str::Utf8Error),
+    #[error("map contains no tiles")]
+    Empty,
+    #[error("line {line}: expected {expected} tiles but found {found}")]
+    RowLength {
+        line: usize,
+        expected: usize,
+        found: usize,
+    },
+    #[error("line {line}, column {column}: unknown tile `{cell}`")]
+    UnknownTile {
+        line: usize,
+        column: usize,
+        cell: String,
+    },
+    #[error("line {line}, column {column}: duplicate `{marker}` marker")]
+    DuplicateMarker {
+        line: usize,
+        column: usize,
+        marker: char,
+    },
+    #[error("line {line}: invalid water setting `{setting}`")]
+    InvalidWaterSetting { line: usize, setting: String },
+    #[error("map has no player spawn")]
+    MissingPlayerSpawn,
+    #[error("map has no portal")]
+    MissingPortal,
+    #[error("invalid Tiled map: {0}")]
+    Tiled(String),
+    #[error("object {id}: unknown object type `{kind}`")]
+    UnknownObject { id: u32, kind: String },
+    #[error("object {id}: `{kind}` is outside of the map")]
+    ObjectOutOfBounds { id: u32, kind: String },
+    #[error("object {id}: duplicate `{kind}` object")]
+    DuplicateObject { id: u32, kind: String },
+}
+
+/// Hashes the contents of a map file. Unlike the standard library's hasher this never changes
+/// between builds, so it can identify a version of a map in files saved by the game.
+pub fn file_hash(bytes: &[u8]) -> u64 {
+    // 64-bit FNV-1a.
+    bytes.iter().fold(0xcbf29ce484222325, |hash, &byte| {
+        (hash ^ byte as u64).wrapping_mul(0x100000001b3)
+    })
+}
+
+impl Map {
+    /// Covers every wall tile with rectangles that do not overlap, merged greedily so walls need
+    /// far fewer colliders and have fewer seams for the player to snag on. Each rectangle is
+    /// grown as wide as it can go and then as tall, so floors and ceilings stay in one piece.
+    pub fn wall_rects(&self) -> Vec<WallRect> {
+        let mut covered = vec![vec![false; self.width]; self.height];
+        let is_free_wall = |covered: &Vec<Vec<bool>>, row: usize, column: usize| {
+            self.tiles[row][column] == Tile::Wall && !covered[row][column]
+        };
+
+        let mut rects = Vec::new();
+        for row in 0..self.height {
+            for column in 0..self.width {
+                if !is_free_wall(&covered, row, column) {
+                    continue;
+                }
+
+                let mut width = 1;
+                while column + width < self.width && is_free_wall(&covered, row, column + width) {
+                    width += 1;
+                }
+                let mut height = 1;
+                while row + height < self.height
+                    && (column..column + width).all(|j| is_free_wall(&covered, row + height, j))
+                {
+                    height += 1;
+                }
+
+                for covered_row in covered.iter_mut().skip(row).take(height) {
+                    for cell in covered_row.iter_mut().skip(column).take(width) {
+                        *cell = true;
+                    }
+                }
+                rects.push(WallRect {
+                    column,
+                    row,
+                    width,
+                    height,
+                });
+            }
+        }
+        rects
+    }
+
+    /// The tile under a point in the world, or `None` if the point is outside the map.
+    pub fn tile_at(&self, point: Vec2) -> Option<Tile> {
+        // Tiles are centered on their position, so round to the nearest one.
+        let column = (point.x / TILE_WIDTH).round();
+        let row = (point.y / TILE_HEIGHT).round();
+        if column < 0. || row < 0. || column >= self.width as f32 || row >= self.height as f32 {
+            return None;
+        }
+        Some(self.tiles[row as usize][column as usize])
+    }
+
+    /// The character representing a cell in the `.map` format.
+    fn cell(&self, position: (u32, u32)) -> char {
+        if self.player_spawn == position {
+            'P'
+        } else if self.portal_spawn == position {
+            'X'
+        } else if self.treasures.contains(&position) {
+            'T'
+        } else if self.enemies.contains(&position) {
+            'E'
+        } else if self.checkpoints.contains(&position) {
+            'C'
+        } else {
+            match self.tiles[position.1 as usize][position.0 as usize] {
+                Tile::Empty => '0',
+                Tile::Wall => '1',
+                Tile::Air => 'A',
+            }
+        }
+    }
 }
 
-#[derive(Default)]
-pub struct MapLoader;
+/// Writes the map in the `.map` format read by `MapLoader`.
+impl fmt::Display for Map {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.water != WaterSettings::default() {
+            writeln!(f, "{}", self.water)?;
+        }
+        for i in (0..self.height).rev() {
+            let row = (0..self.width)
+                .map(|j| self.cell((j as u32, i as u32)).to_string())
+                .collect::<Vec<String>>();
+            writeln!(f, "{}", row.join(" "))?;
+        }
+        Ok(())
+    }
+}
+
+impl FromStr for Map {
+    type Err = MapError;
+
+    fn from_str(map_str: &str) -> Result<Self, Self::Err> {
+        let mut water = WaterSettings::default();
+        for (i, line) in map_str.lines().enumerate() {
+            if !is_water_line(line) {
+                continue;
+            }
+            let words = line.split_whitespace().skip(1);
+            for setting in words {
+                setting
+                    .split_once('=')
+                    .and_then(|(name, value)| water.set(name, value))
+                    .ok_or_else(|| MapError::InvalidWaterSetting {
+                        line: i + 1,
+                        setting: setting.to_string(),
+                    })?;
+            }
+        }
+
+        // Keep each row's position in the file for error reporting.
+        let rows = map_str
+            .lines()
+            .enumerate()
+            .filter(|(_, line)| !line.trim().is_empty() && !is_water_line(line))
+            .map(|(i, line)| (i + 1, cells(line).collect::<Vec<(usize, &str)>>()))
+            .collect::<Vec<(usize, Vec<(usize, &str)>)>>();
+
+        let height = rows.len();
+        let width = match rows.first() {
+            Some((_, cells)) => cells.len(),
+            None => return Err(MapError::Empty),
+        };
+        let mut map = Map {
+            width,
+            height,
+            tiles: vec![vec![Tile::Empty; width]; height],
+            player_spawn: (0, 0),
+            portal_spawn: (0, 0),
+            treasures: vec![],
+            enemies: vec![],
+            checkpoints: vec![],
+            water,
+            file_hash: file_hash(map_str.as_bytes()),
+        };
+        let mut player_spawn = None;
+        let mut portal_spawn = None;
+
+        // Scan rows in file order, so errors point at the later of two conflicting tiles, but
+        // store them bottom to top.
+        for (k, (line, cells)) in rows.iter().enumerate() {
+            let i = height - 1 - k;
+            if cells.len() != width {
+                return Err(MapError::RowLength {
+                    line: *line,
+                    expected: width,
+                    found: cells.len(),
+                });
+            }
+
+            for (j, &(column, cell)) in cells.iter().enumerate() {
+                let position = (j as u32, i as u32);
+                let mut chars = cell.chars();
+                let tile_char = match (chars.next(), chars.next()) {
+                    (Some(c), None) => c,
+                    _ => {
+                        return Err(MapError::UnknownTile {
+                            line: *line,
+                            column,
+                            cell: cell.to_string(),
+                        })
+                    }
+                };
+                map.tiles[i][j] = match tile_char {
+                    '0' => Tile::Empty,
+                    '1' => Tile::Wall,
+                    'A' => Tile::Air,
+                    'P' | 'X' => {
+                        let spawn = if tile_char == 'P' {
+                            &mut player_spawn
+                        } else {
+                            &mut portal_spawn
+                        };
+                        if spawn.replace(position).is_some() {
+                            return Err(MapError::DuplicateMarker {
+                                line: *line,
+                                column,
+                                marker: tile_char,
+                            });
+                        }
+                        Tile::Empty
+                    }
+                    'T' => {
+                        map.treasures.push(position);
+                        Tile::Empty
+                    }
+                    'E' => {
+                        map.enemies.push(position);
+                        Tile::Empty
+                    }
+                    'C' => {
+                        map.checkpoints.push(position);
+                        Tile::Empty
+                    }
+                    _ => {
+                        return Err(MapError::UnknownTile {
+                            line: *line,
+                            column,
+                            cell: cell.to_string(),
+                        })
+                    }
+                }
+            }
+        }
+
+        map.player_spawn = player_spawn.ok_or(MapError::MissingPlayerSpawn)?;
+        map.portal_spawn = portal_spawn.ok_or(MapError::MissingPortal)?;
+        Ok(map)
+    }
+}
+
+/// Whether a line holds water settings rather than a row of tiles.
+fn is_water_line(line: &str) -> bool {
+    line.split_whitespace().next() == Some("water")
+}
+
+/// Splits a line into whitespace-separated cells along with their 1-based column.
+fn cells(line: &str) -> impl Iterator<Item = (usize, &str)> {
+    let mut rest = line;
+    let mut offset = 0;
+    std::iter::from_fn(move || {
+        let start = rest.find(|c: char| !c.is_whitespace())?;
+        let end = rest[start..]
+            .find(char::is_whitespace)
+            .map_or(rest.len(), |len| start + len);
+        let column = line[..offset + start].chars().count() + 1;
+        let cell = &rest[start..end];
+        rest = &rest[end..];
+        offset += end;
+        Some((column, cell))
+    })
+}
+
+/// Errors from maps that failed to load, shared with the `MapLoader` since the asset server
+/// only logs them.
+#[derive(Default, Clone)]
+pub struct MapLoadErrors(Arc<Mutex<Vec<String>>>);
+
+impl MapLoadErrors {
+    pub fn push(&self, error: String) {
+        self.0.lock().unwrap().push(error);
+    }
+
+    pub fn take(&self) -> Vec<String> {
+        std::mem::take(&mut *self.0.lock().unwrap())
+    }
+}
+
+pub struct MapLoader {
+    errors: MapLoadErrors,
+}
+
+impl FromWorld for MapLoader {
+    fn from_world(world: &mut World) -> Self {
+        MapLoader {
+            errors: world
+                .get_resource_or_insert_with(MapLoadErrors::default)
+                .clone(),
+        }
+    }
+}
 
 impl AssetLoader for MapLoader {
     fn load<'a>(
@@ -40,50 +465,20 @@ impl AssetLoader for MapLoader {
         load_context: &'a mut LoadContext,
     ) -> BoxedFuture<'a, Result<(), bevy::asset::Error>> {
         Box::pin(async move {
-            let mut map = Map {
-                tiles: [[Tile::Empty; MAP_WIDTH]; MAP_HEIGHT],
-                player_spawn: (0, 0),
-                portal_spawn: (0, 0),
-                treasures: vec![],
-                enemies: vec![],
-            };
-            let map_str = str::from_utf8(bytes).unwrap().trim();
-            let lines = map_str
-                .lines()
-                .collect::<Vec<&str>>()
-                .into_iter()
-                .rev()
-                .collect::<Vec<&str>>();
-
-            for i in 0..MAP_HEIGHT {
-                let cells: Vec<&str> = lines[i].trim().split_whitespace().collect();
-                for j in 0..MAP_WIDTH {
-                    let tile_char = cells[j].chars().nth(0).unwrap();
-                    map.tiles[i][j] = match tile_char {
-                        '0' => Tile::Empty,
-                        '1' => Tile::Wall,
-                        'P' => {
-                            map.player_spawn = (j as u32, i as u32);
-                            Tile::Empty
-                        }
-                        'T' => {
-                            map.treasures.push((j as u32, i as u32));
-                            Tile::Empty
-                        }
-                        'E' => {
-                            map.enemies.push((j as u32, i as u32));
-                            Tile::Empty
-                        },
-                        'X' => {
-                            map.portal_spawn = (j as u32, i as u32);
-                            Tile::Empty
-                        }
-                        _ => Tile::Empty,
-                    }
+            match str::from_utf8(bytes)
+                .map_err(MapError::from)
+                .and_then(str::parse::<Map>)
+            {
+                Ok(map) => {
+                    load_context.set_default_asset(LoadedAsset::new(map));
+                    Ok(())
+                }
+                Err(err) => {
+                    self.errors
+                        .push(format!("{}: {}", load_context.path().display(), err));
+                    Err(err.into())
                 }
             }
-            load_context.set_default_asset(LoadedAsset::new(map));
-            Ok(())
         })
     }
 
@@ -92,110 +487,231 @@ impl AssetLoader for MapLoader {
     }
 }
 
+/// Marks the screen listing map load errors.
+#[derive(Component)]
+struct MapErrorScreen;
+
+/// Marks entities built from the current map, which are rebuilt when it is reloaded.
+#[derive(Component)]
+pub struct MapEntity;
+
+/// Sent when the current map changes during play, once everything built from the old version
+/// has been despawned.
+pub struct MapReloaded;
+
 pub struct MapPlugin;
 
 impl Plugin for MapPlugin {
     fn build(&self, app: &mut App) {
         app.add_asset::<Map>()
             .init_asset_loader::<MapLoader>()
+            .init_asset_loader::<TiledMapLoader>()
+            .add_asset::<Tileset>()
+            .init_asset_loader::<TilesetLoader>()
+            .add_event::<MapReloaded>()
+            .add_system_set(SystemSet::on_update(AppState::Loading).with_system(show_map_errors))
+            .add_system_set(
+                SystemSet::on_update(AppState::LoadingLevel).with_system(show_map_errors),
+            )
+            .add_system_set(SystemSet::on_exit(AppState::Loading).with_system(setup_music))
             .add_system_set(
                 SystemSet::on_enter(AppState::Game)
                     .with_system(setup_map)
-                    .with_system(setup_boundaries)
-                    .with_system(setup_music),
-            );
-    }
-}
-
-fn setup_map(mut commands: Commands, maps: Res<Assets<Map>>, game_assets: Res<GameAssets>) {
-    let map = maps.get(&game_assets.map).unwrap();
-
-    for i in 0..MAP_HEIGHT {
-        for j in 0..MAP_WIDTH {
-            let tile = map.tiles[i][j];
-            let tile_index = match tile {
-                Tile::Wall => 0,
-                _ => 3,
-            };
-            let mut entity = commands.spawn_bundle(SpriteSheetBundle {
-                texture_atlas: game_assets.tile_set_atlas.clone(),
-                transform: Transform::from_translation(Vec3::new(
-                    TILE_WIDTH * j as f32,
-                    TILE_HEIGHT * i as f32,
+                    .with_system(setup_boundaries),
+            )
+            .add_system_set(SystemSet::on_update(AppState::Game).with_system(reload_map));
+    }
+}
+
+fn setup_map(
+    mut commands: Commands,
+    maps: Res<Assets<Map>>,
+    tilesets: Res<Assets<Tileset>>,
+    game_assets: Res<GameAssets>,
+    level: Res<CurrentLevel>,
+) {
+    let map = maps.get(&level.map).unwrap();
+    let tileset = tilesets.get(&game_assets.tileset).unwrap();
+    spawn_tiles(&mut commands, map, tileset);
+}
+
+fn setup_boundaries(mut commands: Commands, maps: Res<Assets<Map>>, level: Res<CurrentLevel>) {
+    let map = maps.get(&level.map).unwrap();
+    spawn_boundaries(&mut commands, map);
+}
+
+#[allow(clippy::too_many_arguments)]
+fn reload_map(
+    mut commands: Commands,
+    mut map_events: EventReader<AssetEvent<Map>>,
+    mut map_reloaded_events: EventWriter<MapReloaded>,
+    map_entity_query: Query<Entity, With<MapEntity>>,
+    maps: Res<Assets<Map>>,
+    tilesets: Res<Assets<Tileset>>,
+    game_assets: Res<GameAssets>,
+    level: Res<CurrentLevel>,
+) {
+    let modified = map_events.iter().any(|event| match event {
+        AssetEvent::Modified { handle } => handle == &level.map,
+        _ => false,
+    });
+    if !modified {
+        return;
+    }
+
+    for entity in map_entity_query.iter() {
+        commands.entity(entity).despawn_recursive();
+    }
+    let map = maps.get(&level.map).unwrap();
+    let tileset = tilesets.get(&game_assets.tileset).unwrap();
+    spawn_tiles(&mut commands, map, tileset);
+    spawn_boundaries(&mut commands, map);
+    map_reloaded_events.send(MapReloaded);
+}
+
+fn spawn_tiles(commands: &mut Commands, map: &Map, tileset: &Tileset) {
+    for i in 0..map.height {
+        for j in 0..map.width {
+            commands
+                .spawn_bundle(SpriteSheetBundle {
+                    texture_atlas: tileset.atlas.clone(),
+                    transform: Transform::from_translation(Vec3::new(
+                        TILE_WIDTH * j as f32,
+                        TILE_HEIGHT * i as f32,
+                        0.,
+                    )),
+                    sprite: TextureAtlasSprite {
+                        index: tileset.index(map, j, i),
+                        color: tile_color(map.tiles[i][j]),
+                        ..default()
+                    },
+                    ..default()
+                })
+                .insert(LevelEntity)
+                .insert(MapEntity);
+        }
+    }
+
+    for rect in map.wall_rects() {
+        // Tiles are centered on their position, so the rectangle starts half a tile early.
+        let half_width = TILE_WIDTH * rect.width as f32 / 2.;
+        let half_height = TILE_HEIGHT * rect.height as f32 / 2.;
+        commands
+            .spawn()
+            .insert_bundle(TransformBundle {
+                local: Transform::from_translation(Vec3::new(
+                    TILE_WIDTH * (rect.column as f32 - 0.5) + half_width,
+                    TILE_HEIGHT * (rect.row as f32 - 0.5) + half_height,
                     0.,
                 )),
-                sprite: TextureAtlasSprite {
-                    index: tile_index,
-                    ..default()
-                },
                 ..default()
-            });
+            })
+            .insert(Collider::cuboid(half_width, half_height))
+            .insert(LevelEntity)
+            .insert(MapEntity);
+    }
+}
 
-            if tile == Tile::Wall {
-                // Add a little overlap between colliders to prevent player from getting stuck
-                // between tiles.
-                entity.insert(Collider::cuboid(8.1, 8.1));
-            }
-        }
+fn show_map_errors(
+    mut commands: Commands,
+    errors: Res<MapLoadErrors>,
+    asset_server: Res<AssetServer>,
+    mut screen_query: Query<&mut Text, With<MapErrorScreen>>,
+) {
+    let new_errors = errors.take();
+    if new_errors.is_empty() {
+        return;
+    }
+
+    if let Some(mut text) = screen_query.iter_mut().next() {
+        text.sections[0].value.push_str(&new_errors.join("\n"));
+        text.sections[0].value.push('\n');
+        return;
     }
+
+    // Game assets may never finish loading, so the error screen loads its own font.
+    commands
+        .spawn_bundle(
+            TextBundle::from_sections([TextSection::new(
+                format!("{}\n", new_errors.join("\n")),
+                TextStyle {
+                    font: asset_server.load("fonts/FiraSans-Bold.ttf"),
+                    font_size: 30.0,
+                    color: Color::WHITE,
+                },
+            )])
+            .with_style(Style {
+                position_type: PositionType::Absolute,
+                position: UiRect {
+                    top: Val::Px(20.0),
+                    left: Val::Px(20.0),
+                    ..default()
+                },
+                ..default()
+            }),
+        )
+        .insert(MapErrorScreen);
 }
 
-fn setup_boundaries(mut commands: Commands) {
+fn spawn_boundaries(commands: &mut Commands, map: &Map) {
+    let map_width = map.width as f32 * TILE_WIDTH;
+    let map_height = map.height as f32 * TILE_HEIGHT;
+
     // Floor boundary
     commands
         .spawn()
         .insert_bundle(TransformBundle {
             local: Transform {
-                translation: Vec3::new(MAP_WIDTH as f32 * TILE_WIDTH / 2., -TILE_HEIGHT / 2., 0.),
+                translation: Vec3::new(map_width / 2., -TILE_HEIGHT / 2., 0.),
                 ..default()
             },
             ..default()
         })
-        .insert(Collider::cuboid(MAP_WIDTH as f32 * TILE_WIDTH / 2., 0.));
+        .insert(Collider::cuboid(map_width / 2., 0.))
+        .insert(LevelEntity)
+        .insert(MapEntity);
 
     // Ceiling boundary
     commands
         .spawn()
         .insert_bundle(TransformBundle {
             local: Transform {
-                translation: Vec3::new(
-                    MAP_WIDTH as f32 * TILE_WIDTH / 2.,
-                    MAP_HEIGHT as f32 * TILE_HEIGHT - TILE_HEIGHT / 2.,
-                    0.,
-                ),
+                translation: Vec3::new(map_width / 2., map_height - TILE_HEIGHT / 2., 0.),
                 ..default()
             },
             ..default()
         })
-        .insert(Collider::cuboid(MAP_WIDTH as f32 * TILE_WIDTH / 2., 0.));
+        .insert(Collider::cuboid(map_width / 2., 0.))
+        .insert(LevelEntity)
+        .insert(MapEntity);
 
     // Left wall boundary
     commands
         .spawn()
         .insert_bundle(TransformBundle {
             local: Transform {
-                translation: Vec3::new(-TILE_WIDTH / 2., MAP_HEIGHT as f32 * TILE_HEIGHT / 2., 0.),
+                translation: Vec3::new(-TILE_WIDTH / 2., map_height / 2., 0.),
                 ..default()
             },
             ..default()
         })
-        .insert(Collider::cuboid(0., MAP_HEIGHT as f32 * TILE_HEIGHT / 2.));
+        .insert(Collider::cuboid(0., map_height / 2.))
+        .insert(LevelEntity)
+        .insert(MapEntity);
 
     // Right wall boundary
     commands
         .spawn()
         .insert_bundle(TransformBundle {
             local: Transform {
-                translation: Vec3::new(
-                    MAP_WIDTH as f32 * TILE_WIDTH - TILE_WIDTH / 2.,
-                    MAP_HEIGHT as f32 * TILE_HEIGHT / 2.,
-                    0.,
-                ),
+                translation: Vec3::new(map_width - TILE_WIDTH / 2., map_height / 2., 0.),
                 ..default()
             },
             ..default()
         })
-        .insert(Collider::cuboid(0., MAP_HEIGHT as f32 * TILE_HEIGHT / 2.));
+        .insert(Collider::cuboid(0., map_height / 2.))
+        .insert(LevelEntity)
+        .insert(MapEntity);
 }
 
 fn setup_music(game_assets: Res<GameAssets>, audio: Res<Audio>) {
@@ -204,3 +720,162 @@ fn setup_music(game_assets: Res<GameAssets>, audio: Res<Audio>) {
         PlaybackSettings::LOOP.with_volume(0.75),
     );
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse_error(map: &str) -> MapError {
+        map.parse::<Map>().unwrap_err()
+    }
+
+    #[test]
+    fn short_row_reports_its_line() {
+        let error = parse_error(
+            "1 1 1 1
+             1 P X 1
+             1 1 1",
+        );
+        assert!(
+            matches!(
+                error,
+                MapError::RowLength {
+                    line: 3,
+                    expected: 4,
+                    found: 3
+                }
+            ),
+            "{:?}",
+            error
+        );
+    }
+
+    #[test]
+    fn misspelt_water_setting_is_read_as_a_row() {
+        let error = parse_error("1 1 1\n1 P X\n1 1 1\nwaterscale=0.01");
+        assert!(
+            matches!(
+                error,
+                MapError::RowLength {
+                    line: 4,
+                    expected: 3,
+                    found: 1
+                }
+            ),
+            "{:?}",
+            error
+        );
+    }
+
+    #[test]
+    fn unknown_tile_reports_its_line_and_column() {
+        let error = parse_error("1 1 1 1\n1 P Q X\n1 1 1 1");
+        assert!(
+            matches!(
+                &error,
+                MapError::UnknownTile {
+                    line: 2,
+                    column: 5,
+                    cell
+                } if cell == "Q"
+            ),
+            "{:?}",
+            error
+        );
+    }
+
+    #[test]
+    fn second_player_spawn_reports_its_line_and_column() {
+        let error = parse_error("1 1 1 1 1\n1 P X  P 1\n1 1 1 1 1");
+        assert!(
+            matches!(
+                error,
+                MapError::DuplicateMarker {
+                    line: 2,
+                    column: 8,
+                    marker: 'P'
+                }
+            ),
+            "{:?}",
+            error
+        );
+    }
+
+    #[test]
+    fn second_portal_reports_the_later_line() {
+        let error = parse_error("1 1 1 1\n1 X 0 1\n1 P X 1\n1 1 1 1");
+        assert!(
+            matches!(
+                error,
+                MapError::DuplicateMarker {
+                    line: 3,
+                    column: 5,
+                    marker: 'X'
+                }
+            ),
+            "{:?}",
+            error
+        );
+    }
+
+    #[test]
+    fn map_without_player_spawn_is_rejected() {
+        let error = parse_error("1 1 1\n1 X 1\n1 1 1");
+        assert!(matches!(error, MapError::MissingPlayerSpawn), "{:?}", error);
+    }
+
+    #[test]
+    fn map_without_portal_is_rejected() {
+        let error = parse_error("1 1 1\n1 P 1\n1 1 1");
+        assert!(matches!(error, MapError::MissingPortal), "{:?}", error);
+    }
+
+    /// Checks every wall is covered by exactly one of the map's wall rectangles, and nothing else
+    /// is covered at all.
+    fn assert_walls_covered_exactly(map: &Map) {
+        let mut coverage = vec![vec![0; map.width]; map.height];
+        for rect in map.wall_rects() {
+            for row in coverage.iter_mut().skip(rect.row).take(rect.height) {
+                for cell in row.iter_mut().skip(rect.column).take(rect.width) {
+                    *cell += 1;
+                }
+            }
+        }
+        for (row, tiles) in map.tiles.iter().enumerate() {
+            for (column, &tile) in tiles.iter().enumerate() {
+                let expected = if tile == Tile::Wall { 1 } else { 0 };
+                assert_eq!(
+                    coverage[row][column], expected,
+                    "cell ({}, {}) covered {} times",
+                    column, row, coverage[row][column]
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn l_shaped_wall_is_covered_exactly() {
+        let map: Map = "1 0 0 0
+                        1 0 P 0
+                        1 0 0 X
+                        1 1 1 1"
+            .parse()
+            .unwrap();
+        assert_walls_covered_exactly(&map);
+        // The floor first, then the rest of the upright above it.
+        assert_eq!(map.wall_rects().len(), 2);
+    }
+
+    #[test]
+    fn hollow_wall_is_covered_exactly() {
+        let map: Map = "1 1 1 1 1
+                        1 0 0 0 1
+                        1 P 0 X 1
+                        1 1 1 1 1"
+            .parse()
+            .unwrap();
+        assert_walls_covered_exactly(&map);
+        // The floor and ceiling, and the two sides between them.
+        assert_eq!(map.wall_rects().len(), 4);
+    }
+}
@@ -0,0 +1,556 @@
+use super::accessibility::{AccessibilitySettings, Distortion, MessageStyle};
+use super::app::AppState;
+use super::assets::GameAssets;
+use super::campaign::{Campaign, CurrentLevel};
+use super::input::{Action, Actions, InputBindings};
+use super::replay::{Recording, Replay};
+use bevy::{app::AppExit, prelude::*};
+use bevy_rapier2d::prelude::*;
+
+const SELECTED_COLOR: Color = Color::WHITE;
+const UNSELECTED_COLOR: Color = Color::GRAY;
+
+#[derive(Clone, Copy, PartialEq)]
+enum MenuAction {
+    Play,
+    Edit,
+    Accessibility,
+    Distortion,
+    Messages,
+    Contrast,
+    Controls,
+    Rebind(Action),
+    ResetBindings,
+    Back,
+    Resume,
+    Restart,
+    NextLevel,
+    PlayAgain,
+    WatchReplay,
+    MainMenu,
+    Quit,
+}
+
+/// Root node of a menu screen, tracking which item is highlighted.
+#[derive(Component)]
+struct Menu {
+    selected: usize,
+    /// The action waiting for a key or button to be bound to it, if any.
+    rebinding: Option<Action>,
+}
+
+#[derive(Component)]
+struct MenuItem {
+    index: usize,
+    action: MenuAction,
+}
+
+pub struct MenuPlugin;
+
+impl Plugin for MenuPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_system_set(SystemSet::on_enter(AppState::MainMenu).with_system(setup_main_menu))
+            .add_system_set(SystemSet::on_enter(AppState::Paused).with_system(setup_pause_menu))
+            .add_system_set(
+                SystemSet::on_enter(AppState::GameOver).with_system(setup_game_over_menu),
+            )
+            .add_system_set(
+                SystemSet::on_enter(AppState::LevelComplete).with_system(setup_level_complete_menu),
+            )
+            .add_system_set(
+                SystemSet::on_enter(AppState::Accessibility).with_system(setup_accessibility_menu),
+            )
+            .add_system_set(
+                SystemSet::on_enter(AppState::Controls).with_system(setup_controls_menu),
+            )
+            .add_system_set(SystemSet::on_update(AppState::Controls).with_system(rebind_action))
+            .add_system_set(SystemSet::on_update(AppState::Game).with_system(pause_game))
+            .add_system_set(SystemSet::on_update(AppState::Paused).with_system(resume_game))
+            .add_system_set(SystemSet::on_pause(AppState::Game).with_system(freeze_physics))
+            .add_system_set(SystemSet::on_resume(AppState::Game).with_system(resume_physics));
+
+        for state in [
+            AppState::MainMenu,
+            AppState::Paused,
+            AppState::GameOver,
+            AppState::LevelComplete,
+            AppState::Accessibility,
+            AppState::Controls,
+        ] {
+            app.add_system_set(
+                SystemSet::on_update(state.clone())
+                    .with_system(navigate_menu)
+                    .with_system(select_menu_item),
+            )
+            .add_system_set(SystemSet::on_exit(state).with_system(despawn_menu));
+        }
+    }
+}
+
+fn setup_main_menu(commands: Commands, game_assets: Res<GameAssets>) {
+    spawn_menu(
+        commands,
+        &game_assets,
+        Some("ACQUIRE\nCURRENCY."),
+        &[
+            ("PLAY", MenuAction::Play),
+            ("LEVEL EDITOR", MenuAction::Edit),
+            ("CONTROLS", MenuAction::Controls),
+            ("ACCESSIBILITY", MenuAction::Accessibility),
+            ("QUIT", MenuAction::Quit),
+        ],
+        Color::rgb(0., 0.1, 0.2),
+    );
+}
+
+fn setup_pause_menu(commands: Commands, game_assets: Res<GameAssets>) {
+    spawn_menu(
+        commands,
+        &game_assets,
+        Some("PAUSED."),
+        &[
+            ("RESUME", MenuAction::Resume),
+            ("RESTART LEVEL", MenuAction::Restart),
+            ("EDIT LEVEL", MenuAction::Edit),
+            ("MAIN MENU", MenuAction::MainMenu),
+        ],
+        Color::rgba(0., 0., 0., 0.5),
+    );
+}
+
+fn setup_accessibility_menu(
+    commands: Commands,
+    game_assets: Res<GameAssets>,
+    settings: Res<AccessibilitySettings>,
+) {
+    let labels = [
+        MenuAction::Distortion,
+        MenuAction::Messages,
+        MenuAction::Contrast,
+    ]
+    .map(|action| {
+        (
+            setting_label(action, &settings, &default()).unwrap(),
+            action,
+        )
+    });
+    let mut items = labels
+        .iter()
+        .map(|(label, action)| (label.as_str(), *action))
+        .collect::<Vec<(&str, MenuAction)>>();
+    items.push(("BACK", MenuAction::Back));
+    spawn_menu(
+        commands,
+        &game_assets,
+        Some("ACCESSIBILITY."),
+        &items,
+        Color::rgb(0., 0.1, 0.2),
+    );
+}
+
+fn setup_controls_menu(
+    commands: Commands,
+    game_assets: Res<GameAssets>,
+    bindings: Res<InputBindings>,
+) {
+    let labels = Action::ALL.map(|action| {
+        let item = MenuAction::Rebind(action);
+        (setting_label(item, &default(), &bindings).unwrap(), item)
+    });
+    let mut items = labels
+        .iter()
+        .map(|(label, action)| (label.as_str(), *action))
+        .collect::<Vec<(&str, MenuAction)>>();
+    items.push(("RESET TO DEFAULTS", MenuAction::ResetBindings));
+    items.push(("BACK", MenuAction::Back));
+    spawn_menu(
+        commands,
+        &game_assets,
+        Some("CONTROLS."),
+        &items,
+        Color::rgb(0., 0.1, 0.2),
+    );
+}
+
+/// The label of a menu item that changes a setting, showing its current value.
+fn setting_label(
+    action: MenuAction,
+    settings: &AccessibilitySettings,
+    bindings: &InputBindings,
+) -> Option<String> {
+    let label = match action {
+        MenuAction::Rebind(action) => {
+            format!("{}: {}", action.name(), bindings.get(action).describe())
+        }
+        MenuAction::Distortion => format!(
+            "WATER DISTORTION: {}",
+            match settings.distortion {
+                Distortion::Full => "FULL",
+                Distortion::Reduced => "REDUCED",
+                Distortion::Off => "OFF",
+            }
+        ),
+        MenuAction::Messages => format!(
+            "MESSAGES: {}",
+            match settings.messages {
+                MessageStyle::Flash => "FLASH",
+                MessageStyle::Toast => "NO FLASHING",
+            }
+        ),
+        MenuAction::Contrast => format!(
+            "SCREEN CONTRAST: {}",
+            if settings.cap_contrast {
+                "LIMITED"
+            } else {
+                "FULL"
+            }
+        ),
+        _ => return None,
+    };
+    Some(label)
+}
+
+fn setup_game_over_menu(commands: Commands, game_assets: Res<GameAssets>) {
+    // The level already shows how the player died, so only offer the choices.
+    spawn_menu(
+        commands,
+        &game_assets,
+        None,
+        &[
+            ("RETRY", MenuAction::Restart),
+            ("WATCH REPLAY", MenuAction::WatchReplay),
+            ("MAIN MENU", MenuAction::MainMenu),
+        ],
+        Color::NONE,
+    );
+}
+
+fn setup_level_complete_menu(
+    commands: Commands,
+    game_assets: Res<GameAssets>,
+    campaigns: Res<Assets<Campaign>>,
+    level: Res<CurrentLevel>,
+) {
+    let campaign = campaigns.get(&game_assets.campaign).unwrap();
+    if level.is_last(campaign) {
+        spawn_menu(
+            commands,
+            &game_assets,
+            Some("VICTORY."),
+            &[
+                ("PLAY AGAIN", MenuAction::PlayAgain),
+                ("WATCH REPLAY", MenuAction::WatchReplay),
+                ("MAIN MENU", MenuAction::MainMenu),
+            ],
+            Color::rgba(0., 0., 0., 0.5),
+        );
+    } else {
+        spawn_menu(
+            commands,
+            &game_assets,
+            Some("SUCCESS."),
+            &[
+                ("NEXT LEVEL", MenuAction::NextLevel),
+                ("WATCH REPLAY", MenuAction::WatchReplay),
+                ("MAIN MENU", MenuAction::MainMenu),
+            ],
+            Color::rgba(0., 0., 0., 0.5),
+        );
+    }
+}
+
+fn spawn_menu(
+    mut commands: Commands,
+    game_assets: &GameAssets,
+    title: Option<&str>,
+    items: &[(&str, MenuAction)],
+    background: Color,
+) {
+    let mut node = commands.spawn_bundle(NodeBundle {
+        style: Style {
+            size: Size::new(Val::Percent(100.0), Val::Percent(100.0)),
+            position_type: PositionType::Absolute,
+            flex_direction: FlexDirection::ColumnReverse,
+            justify_content: if title.is_some() {
+                JustifyContent::Center
+            } else {
+                JustifyContent::FlexEnd
+            },
+            align_items: AlignItems::Center,
+            padding: UiRect {
+                bottom: Val::Px(50.0),
+                ..default()
+            },
+            ..default()
+        },
+        color: UiColor(background),
+        ..default()
+    });
+    node.add_children(|parent| {
+        if let Some(title) = title {
+            parent.spawn_bundle(
+                TextBundle::from_section(
+                    title,
+                    TextStyle {
+                        font: game_assets.ui_font.clone(),
+                        font_size: 200.0,
+                        color: Color::WHITE,
+                    },
+                )
+                .with_text_alignment(TextAlignment::CENTER)
+                .with_style(Style {
+                    margin: UiRect {
+                        bottom: Val::Px(50.0),
+                        ..default()
+                    },
+                    ..default()
+                }),
+            );
+        }
+
+        for (index, (label, action)) in items.iter().enumerate() {
+            parent
+                .spawn_bundle(
+                    TextBundle::from_section(
+                        *label,
+                        TextStyle {
+                            font: game_assets.ui_font.clone(),
+                            font_size: 60.0,
+                            color: if index == 0 {
+                                SELECTED_COLOR
+                            } else {
+                                UNSELECTED_COLOR
+                            },
+                        },
+                    )
+                    .with_text_alignment(TextAlignment::CENTER),
+                )
+                .insert(MenuItem {
+                    index,
+                    action: *action,
+                });
+        }
+    });
+    node.insert(Menu {
+        selected: 0,
+        rebinding: None,
+    });
+}
+
+fn navigate_menu(
+    keyboard_input: Res<Input<KeyCode>>,
+    mut menu_query: Query<&mut Menu>,
+    mut item_query: Query<(&MenuItem, &mut Text)>,
+) {
+    let item_count = item_query.iter().count();
+    for mut menu in menu_query.iter_mut() {
+        if menu.rebinding.is_some() {
+            continue;
+        }
+        if keyboard_input.just_pressed(KeyCode::Up) {
+            menu.selected = (menu.selected + item_count - 1) % item_count;
+        } else if keyboard_input.just_pressed(KeyCode::Down) {
+            menu.selected = (menu.selected + 1) % item_count;
+        } else {
+            continue;
+        }
+
+        for (item, mut text) in item_query.iter_mut() {
+            text.sections[0].style.color = if item.index == menu.selected {
+                SELECTED_COLOR
+            } else {
+                UNSELECTED_COLOR
+            };
+        }
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
+fn select_menu_item(
+    mut state: ResMut<State<AppState>>,
+    mut keyboard_input: ResMut<Input<KeyCode>>,
+    mut app_exit_events: EventWriter<AppExit>,
+    mut menu_query: Query<&mut Menu>,
+    mut item_query: Query<(&MenuItem, &mut Text)>,
+    campaigns: Res<Assets<Campaign>>,
+    game_assets: Res<GameAssets>,
+    mut level: ResMut<CurrentLevel>,
+    mut settings: ResMut<AccessibilitySettings>,
+    mut bindings: ResMut<InputBindings>,
+    mut replay: ResMut<Replay>,
+) {
+    let mut menu = match menu_query.iter_mut().next() {
+        Some(menu) if menu.rebinding.is_none() => menu,
+        _ => return,
+    };
+
+    // Escape backs out of the pause menu, and quits from the main menu.
+    let action = if keyboard_input.clear_just_pressed(KeyCode::Escape) {
+        match state.current() {
+            AppState::Paused => MenuAction::Resume,
+            AppState::MainMenu => MenuAction::Quit,
+            AppState::Accessibility | AppState::Controls => MenuAction::Back,
+            _ => return,
+        }
+    } else if keyboard_input.clear_just_pressed(KeyCode::Return)
+        || keyboard_input.clear_just_pressed(KeyCode::Space)
+    {
+        match item_query
+            .iter()
+            .find(|(item, _)| item.index == menu.selected)
+        {
+            Some((item, _)) => item.action,
+            None => return,
+        }
+    } else {
+        return;
+    };
+
+    let campaign = campaigns.get(&game_assets.campaign).unwrap();
+    match action {
+        MenuAction::Play => {
+            *level = CurrentLevel::first(campaign);
+            state.set(AppState::LoadingLevel).unwrap();
+        }
+        MenuAction::Edit => {
+            // From the main menu, start editing the first level; when paused, edit this one.
+            if state.current() == &AppState::MainMenu {
+                *level = CurrentLevel::first(campaign);
+                state.set(AppState::Editor).unwrap();
+            } else {
+                state.replace(AppState::Editor).unwrap();
+            }
+        }
+        MenuAction::Accessibility => state.set(AppState::Accessibility).unwrap(),
+        MenuAction::Distortion => {
+            settings.distortion = match settings.distortion {
+                Distortion::Full => Distortion::Reduced,
+                Distortion::Reduced => Distortion::Off,
+                Distortion::Off => Distortion::Full,
+            }
+        }
+        MenuAction::Messages => {
+            settings.messages = match settings.messages {
+                MessageStyle::Flash => MessageStyle::Toast,
+                MessageStyle::Toast => MessageStyle::Flash,
+            }
+        }
+        MenuAction::Contrast => settings.cap_contrast = !settings.cap_contrast,
+        MenuAction::Controls => state.set(AppState::Controls).unwrap(),
+        MenuAction::Rebind(action) => {
+            menu.rebinding = Some(action);
+            for (item, mut text) in item_query.iter_mut() {
+                if item.index == menu.selected {
+                    text.sections[0].value = format!("{}: PRESS A KEY OR BUTTON", action.name());
+                }
+            }
+        }
+        MenuAction::ResetBindings => *bindings = default(),
+        MenuAction::Back => state.set(AppState::MainMenu).unwrap(),
+        MenuAction::Resume => state.pop().unwrap(),
+        MenuAction::Restart => state.replace(AppState::LoadingLevel).unwrap(),
+        MenuAction::NextLevel => {
+            level.advance(campaign);
+            state.replace(AppState::LoadingLevel).unwrap();
+        }
+        MenuAction::PlayAgain => {
+            *level = CurrentLevel::first(campaign);
+            state.replace(AppState::LoadingLevel).unwrap();
+        }
+        MenuAction::WatchReplay => {
+            // The saved run is normally this level's, but play whichever level it was from.
+            let recording = Recording::load();
+            let replay_level = recording
+                .as_ref()
+                .and_then(|recording| CurrentLevel::at(campaign, recording.level));
+            if let (Some(recording), Some(replay_level)) = (recording, replay_level) {
+                *level = replay_level;
+                *replay = Replay::play(recording);
+                state.replace(AppState::LoadingLevel).unwrap();
+            }
+        }
+        MenuAction::MainMenu => state.replace(AppState::MainMenu).unwrap(),
+        MenuAction::Quit => app_exit_events.send(AppExit),
+    }
+
+    if settings.is_changed() || bindings.is_changed() {
+        relabel_items(&mut item_query, &settings, &bindings);
+    }
+}
+
+/// Binds the action waiting for a binding to the next key or gamepad button pressed, in place
+/// of any keys or buttons bound to it before.
+fn rebind_action(
+    mut keyboard_input: ResMut<Input<KeyCode>>,
+    mut gamepad_buttons: ResMut<Input<GamepadButton>>,
+    mut menu_query: Query<&mut Menu>,
+    mut item_query: Query<(&MenuItem, &mut Text)>,
+    settings: Res<AccessibilitySettings>,
+    mut bindings: ResMut<InputBindings>,
+) {
+    let mut menu = match menu_query.iter_mut().next() {
+        Some(menu) => menu,
+        None => return,
+    };
+    let action = match menu.rebinding {
+        Some(action) => action,
+        None => return,
+    };
+
+    // Clear the press so it does not also act on the menu.
+    let key = keyboard_input.get_just_pressed().next().copied();
+    let button = gamepad_buttons.get_just_pressed().next().copied();
+    if let Some(key) = key {
+        keyboard_input.clear_just_pressed(key);
+        bindings.get_mut(action).keys = vec![key];
+    } else if let Some(button) = button {
+        gamepad_buttons.clear_just_pressed(button);
+        bindings.get_mut(action).buttons = vec![button.button_type];
+    } else {
+        return;
+    }
+    menu.rebinding = None;
+    relabel_items(&mut item_query, &settings, &bindings);
+}
+
+fn relabel_items(
+    item_query: &mut Query<(&MenuItem, &mut Text)>,
+    settings: &AccessibilitySettings,
+    bindings: &InputBindings,
+) {
+    for (item, mut text) in item_query.iter_mut() {
+        if let Some(label) = setting_label(item.action, settings, bindings) {
+            text.sections[0].value = label;
+        }
+    }
+}
+
+fn despawn_menu(mut commands: Commands, query: Query<Entity, With<Menu>>) {
+    for entity in query.iter() {
+        commands.entity(entity).despawn_recursive();
+    }
+}
+
+fn pause_game(mut state: ResMut<State<AppState>>, mut actions: Actions) {
+    if actions.clear_just_pressed(Action::Pause) {
+        // The level may have just ended, in which case its own transition takes priority.
+        let _ = state.push(AppState::Paused);
+    }
+}
+
+fn resume_game(mut state: ResMut<State<AppState>>, mut actions: Actions) {
+    if actions.clear_just_pressed(Action::Pause) {
+        // The menu may have resumed or restarted on this same frame, in which case it wins.
+        let _ = state.pop();
+    }
+}
+
+fn freeze_physics(mut rapier_config: ResMut<RapierConfiguration>) {
+    rapier_config.physics_pipeline_active = false;
+    rapier_config.query_pipeline_active = false;
+}
+
+fn resume_physics(mut rapier_config: ResMut<RapierConfiguration>) {
+    rapier_config.physics_pipeline_active = true;
+    rapier_config.query_pipeline_active = true;
+}
@@ -0,0 +1,208 @@
+use super::animation::{Animation, AnimationState};
+use super::app::AppState;
+use super::assets::GameAssets;
+use super::campaign::{CurrentLevel, LevelEntity};
+use super::health::Deaths;
+use super::map::{Map, Tile};
+use super::player::{Player, PlayerAnimations};
+use super::tick::{self, TickStage, TICK};
+use bevy::{prelude::*, render::view::Visibility};
+
+/// How long the player can hold their breath, in seconds.
+const OXYGEN_CAPACITY: f32 = 30.;
+
+/// How many seconds of breath an air pocket gives back for every second spent in it.
+const REFILL_RATE: f32 = 15.;
+
+/// How many bubbles the gauge shows when the player's lungs are full.
+const GAUGE_BUBBLES: usize = 10;
+
+/// How opaque the bubbles already breathed are drawn.
+const SPENT_BUBBLE_ALPHA: f32 = 0.2;
+
+/// Seconds of breath the player has left before they drown.
+#[derive(Component)]
+pub struct Oxygen(pub f32);
+
+impl Default for Oxygen {
+    fn default() -> Self {
+        Oxygen(OXYGEN_CAPACITY)
+    }
+}
+
+/// Marks the row of bubbles showing how much breath the player has left.
+#[derive(Component)]
+struct OxygenGauge;
+
+pub struct OxygenPlugin;
+
+impl Plugin for OxygenPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_system_set(SystemSet::on_enter(AppState::Game).with_system(setup_gauge))
+            .add_system_set(SystemSet::on_update(AppState::Game).with_system(update_gauge));
+        tick::add_systems(
+            app,
+            TickStage::Gameplay,
+            SystemSet::new().with_system(breathe),
+        );
+    }
+}
+
+fn setup_gauge(mut commands: Commands, game_assets: Res<GameAssets>) {
+    let bubble = TextSection::new(
+        "O",
+        TextStyle {
+            font: game_assets.ui_font.clone(),
+            font_size: 40.0,
+            color: Color::WHITE,
+        },
+    );
+    commands
+        .spawn_bundle(
+            TextBundle::from_sections(vec![bubble; GAUGE_BUBBLES]).with_style(Style {
+                position_type: PositionType::Absolute,
+                position: UiRect {
+                    top: Val::Px(20.0),
+                    left: Val::Px(20.0),
+                    ..default()
+                },
+                ..default()
+            }),
+        )
+        .insert(OxygenGauge)
+        .insert(LevelEntity);
+}
+
+fn update_gauge(
+    oxygen_query: Query<&Oxygen, With<Player>>,
+    mut gauge_query: Query<&mut Text, With<OxygenGauge>>,
+) {
+    let oxygen = match oxygen_query.get_single() {
+        Ok(oxygen) => oxygen,
+        Err(_) => return,
+    };
+    // A bubble lasts until the breath it stands for has been used up.
+    let full_bubbles = (oxygen.0 / OXYGEN_CAPACITY * GAUGE_BUBBLES as f32).ceil() as usize;
+    for mut text in gauge_query.iter_mut() {
+        for (i, section) in text.sections.iter_mut().enumerate() {
+            let alpha = if i < full_bubbles {
+                1.
+            } else {
+                SPENT_BUBBLE_ALPHA
+            };
+            section.style.color.set_a(alpha);
+        }
+    }
+}
+
+/// The parts of the player that breathing changes.
+type BreathingPlayer = (
+    Entity,
+    &'static Transform,
+    &'static mut Oxygen,
+    &'static mut Animation,
+    &'static mut AnimationState,
+    &'static Visibility,
+);
+
+/// Refills the player's oxygen in air pockets and uses it up everywhere else, costing them a life
+/// once it runs out.
+fn breathe(
+    mut deaths: Deaths,
+    mut player_query: Query<BreathingPlayer, With<Player>>,
+    maps: Res<Assets<Map>>,
+    level: Res<CurrentLevel>,
+    player_animations: Res<PlayerAnimations>,
+) {
+    let (player, transform, mut oxygen, mut animation, mut animation_state, visibility) =
+        player_query.single_mut();
+    if animation.0 == player_animations.dead || !visibility.is_visible {
+        return;
+    }
+
+    let map = maps.get(&level.map).unwrap();
+    if map.tile_at(transform.translation.truncate()) == Some(Tile::Air) {
+        oxygen.0 = (oxygen.0 + REFILL_RATE * TICK).min(OXYGEN_CAPACITY);
+        return;
+    }
+    oxygen.0 -= TICK;
+    if oxygen.0 <= 0. {
+        oxygen.0 = 0.;
+        deaths.kill(
+            player,
+            &mut animation,
+            &mut animation_state,
+            "YOU\nDROWNED.",
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::harness::{Harness, SWIM_RIGHT};
+    use crate::health::Lives;
+    use crate::replay::TickInput;
+
+    const LEVEL: &str = "1 1 1 1 1 1
+                         1 0 0 0 0 1
+                         1 P 0 0 X 1
+                         1 1 1 1 1 1";
+
+    #[test]
+    fn running_out_of_oxygen_costs_a_life() {
+        let mut harness = Harness::new(LEVEL);
+        let spawn = harness.single::<&Transform, With<Player>>().translation;
+        harness.run(60, SWIM_RIGHT);
+
+        harness.run_until((OXYGEN_CAPACITY / TICK) as usize, default(), |world| {
+            world.resource::<Lives>().0 < Lives::default().0
+        });
+        harness.run(1, default());
+        let (transform, oxygen) = harness.single::<(&Transform, &Oxygen), With<Player>>();
+        assert_eq!(
+            transform.translation.x, spawn.x,
+            "should be back at the spawn"
+        );
+        assert!(oxygen.0 > OXYGEN_CAPACITY - 1.);
+        assert!(!Harness::player_is_dead(&mut harness.app.world));
+    }
+
+    #[test]
+    fn drowning_on_last_life_ends_level() {
+        let mut harness = Harness::new(LEVEL);
+        harness.app.world.insert_resource(Lives(1));
+        let ticks = (OXYGEN_CAPACITY / TICK) as usize;
+        harness.run(ticks - 10, default());
+        assert!(!Harness::player_is_dead(&mut harness.app.world));
+
+        harness.run_until(20, default(), Harness::player_is_dead);
+        assert_eq!(harness.single::<&Oxygen, ()>().0, 0.);
+        harness.run_until(240, default(), |world| {
+            world.resource::<State<AppState>>().current() == &AppState::GameOver
+        });
+    }
+
+    #[test]
+    fn air_pocket_refills_oxygen() {
+        let mut harness = Harness::new(
+            "1 1 1 1 1 1
+             1 0 0 0 0 1
+             1 A P 0 X 1
+             1 1 1 1 1 1",
+        );
+        harness.run(600, default());
+        let held = harness.single::<&Oxygen, ()>().0;
+        assert!(held < OXYGEN_CAPACITY - 9.);
+
+        harness.run_until(
+            120,
+            TickInput {
+                move_left: 1.,
+                ..default()
+            },
+            |world| world.query::<&Oxygen>().single(world).0 == OXYGEN_CAPACITY,
+        );
+        assert!(!Harness::player_is_dead(&mut harness.app.world));
+    }
+}
@@ -1,11 +1,18 @@
+use super::accessibility::{AccessibilitySettings, MessageStyle};
 use super::animation::{Animation, AnimationData, AnimationState};
 use super::app::AppState;
 use super::assets::GameAssets;
-use super::map::{Map, TILE_HEIGHT, TILE_WIDTH};
+use super::campaign::{CurrentLevel, LevelEntity};
+use super::health::Health;
+use super::input::{Action, Actions};
+use super::map::{Map, MapReloaded, Tile, TILE_HEIGHT, TILE_WIDTH};
+use super::oxygen::Oxygen;
 use super::portal::Portal;
-use super::treasure::Treasure;
+use super::replay::{GameRng, TickInput};
+use super::tick::{self, TickStage, TICK};
+use super::treasure::{CollectedTreasure, Treasure};
 use benimator::Frame;
-use bevy::{input::keyboard::KeyboardInput, prelude::*, render::view::Visibility};
+use bevy::{ecs::system::EntityCommands, prelude::*, render::view::Visibility};
 use bevy_rapier2d::prelude::*;
 use rand::seq::SliceRandom;
 use rand::Rng;
@@ -16,9 +23,33 @@ const VEL_THRESHOLD: f32 = 0.001;
 // How long to show message
 const MSG_THRESHOLD: Duration = Duration::from_millis(200);
 
+// How long to show a message when messages are not flashed
+const TOAST_DURATION: Duration = Duration::from_secs(2);
+
 // Percentage of time that a message pops up when collecting treasure.
 const MSG_FREQUENCY: u32 = 20;
 
+/// Tuning for horizontal swimming. Speeds are in pixels per second.
+pub struct SwimSettings {
+    /// How quickly the player speeds up while a direction is held, in pixels per second squared.
+    pub acceleration: f32,
+    /// Fastest horizontal swimming speed, in pixels per second.
+    pub max_speed: f32,
+    /// Exponential decay rate of horizontal speed once the player stops swimming.
+    pub drag: f32,
+}
+
+impl Default for SwimSettings {
+    fn default() -> Self {
+        SwimSettings {
+            acceleration: 120.,
+            // Matches the old fixed nudge of 0.2 pixels a frame at 60 frames per second.
+            max_speed: 12.,
+            drag: 4.,
+        }
+    }
+}
+
 #[derive(Component)]
 pub struct Player;
 
@@ -28,6 +59,7 @@ struct Jumper {
     grounded: bool,
 }
 
+/// A message shown when collecting treasure, removed once the time it holds has passed.
 #[derive(Component)]
 struct Message(Duration);
 
@@ -51,26 +83,30 @@ struct PlayerDirection(Direction);
 #[derive(Component)]
 struct Instructions;
 
-#[derive(Component)]
-struct Success;
-
 pub struct PlayerPlugin;
 
 impl Plugin for PlayerPlugin {
     fn build(&self, app: &mut App) {
-        app.add_system_set(
-            SystemSet::on_enter(AppState::Game)
-                .with_system(setup_instructions)
-                .with_system(setup_player),
-        )
-        .add_system_set(
-            SystemSet::on_update(AppState::Game)
+        app.init_resource::<SwimSettings>()
+            .add_system_set(
+                SystemSet::on_enter(AppState::Game)
+                    .with_system(setup_instructions)
+                    .with_system(setup_player),
+            )
+            .add_system_set(
+                SystemSet::on_update(AppState::Game)
+                    .with_system(despawn_messages)
+                    .with_system(despawn_instructions)
+                    .with_system(reload_player),
+            );
+        tick::add_systems(
+            app,
+            TickStage::Gameplay,
+            SystemSet::new()
                 .with_system(player_movement)
                 .with_system(jump_reset)
                 .with_system(collect_treasure)
-                .with_system(enter_portal)
-                .with_system(despawn_messages)
-                .with_system(despawn_instructions),
+                .with_system(enter_portal),
         );
     }
 }
@@ -107,17 +143,19 @@ fn setup_instructions(mut commands: Commands, game_assets: Res<GameAssets>) {
             )
             .id()
     });
-    node.insert(Instructions);
+    node.insert(Instructions).insert(LevelEntity);
 }
 
 fn despawn_instructions(
     mut commands: Commands,
     ins_query: Query<Entity, With<Instructions>>,
-    mut keyboard_input_events: EventReader<KeyboardInput>,
+    actions: Actions,
 ) {
-    for _event in keyboard_input_events.iter() {
-        if !ins_query.iter().next().is_none() {
-            let instructions = ins_query.single();
+    if Action::ALL
+        .into_iter()
+        .any(|action| actions.just_pressed(action))
+    {
+        for instructions in ins_query.iter() {
             commands.entity(instructions).despawn_recursive();
         }
     }
@@ -127,6 +165,7 @@ fn setup_player(
     mut commands: Commands,
     game_assets: Res<GameAssets>,
     maps: Res<Assets<Map>>,
+    level: Res<CurrentLevel>,
     mut animations: ResMut<Assets<AnimationData>>,
 ) {
     let idle = AnimationData(benimator::Animation::from_range(
@@ -169,7 +208,7 @@ fn setup_player(
         dead: dead_handle.clone(),
     });
 
-    let map = maps.get(&game_assets.map).unwrap();
+    let map = maps.get(&level.map).unwrap();
     commands
         .spawn_bundle(SpriteSheetBundle {
             texture_atlas: game_assets.player_atlas.clone(),
@@ -185,11 +224,14 @@ fn setup_player(
             ..default()
         })
         .insert(Player)
+        .insert(LevelEntity)
         .insert(PlayerDirection(Direction::Left))
         .insert(Jumper {
             cooldown: true,
             grounded: true,
         })
+        .insert(Health::default())
+        .insert(Oxygen::default())
         .insert(Animation(idle_handle.clone()))
         .insert(AnimationState::default())
         .insert(Collider::cuboid(7.0, 8.0))
@@ -203,25 +245,48 @@ fn setup_player(
         .insert(LockedAxes::ROTATION_LOCKED);
 }
 
+fn reload_player(
+    mut map_reloaded_events: EventReader<MapReloaded>,
+    mut player_query: Query<(&mut Transform, &mut Velocity), With<Player>>,
+    maps: Res<Assets<Map>>,
+    level: Res<CurrentLevel>,
+) {
+    if map_reloaded_events.iter().count() == 0 {
+        return;
+    }
+
+    // Stay put unless the player is now inside a wall or off the edge of the map.
+    let map = maps.get(&level.map).unwrap();
+    let (mut transform, mut velocity) = player_query.single_mut();
+    if matches!(
+        map.tile_at(transform.translation.truncate()),
+        Some(Tile::Empty | Tile::Air)
+    ) {
+        return;
+    }
+    transform.translation.x = TILE_WIDTH * map.player_spawn.0 as f32;
+    transform.translation.y = TILE_HEIGHT * map.player_spawn.1 as f32;
+    velocity.linvel = Vec2::ZERO;
+}
+
+/// The parts of the player that swimming changes.
+type SwimmingPlayer = (
+    &'static mut Animation,
+    &'static mut AnimationState,
+    &'static mut TextureAtlasSprite,
+    &'static mut Velocity,
+    &'static mut PlayerDirection,
+    &'static mut Jumper,
+    &'static Visibility,
+);
+
 fn player_movement(
-    keyboard_input: Res<Input<KeyCode>>,
+    input: Res<TickInput>,
     animations: Res<PlayerAnimations>,
-    mut players: Query<
-        (
-            &mut Transform,
-            &mut Animation,
-            &mut AnimationState,
-            &mut TextureAtlasSprite,
-            &mut Velocity,
-            &mut PlayerDirection,
-            &mut Jumper,
-            &Visibility,
-        ),
-        (With<RigidBody>, With<Player>),
-    >,
+    swim_settings: Res<SwimSettings>,
+    mut players: Query<SwimmingPlayer, (With<RigidBody>, With<Player>)>,
 ) {
     for (
-        mut transform,
         mut animation,
         mut animation_state,
         mut sprite,
@@ -234,9 +299,9 @@ fn player_movement(
         if animation.0 == animations.dead || !visibility.is_visible {
             return;
         }
-        if keyboard_input.pressed(KeyCode::Space) {
+        if input.swim_up {
             if !jumper.cooldown {
-                velocity.linvel = Vec2::new(0., 50.);
+                velocity.linvel.y = 50.;
                 jumper.cooldown = true;
                 jumper.grounded = false;
                 animation.0 = animations.jump.clone();
@@ -246,22 +311,15 @@ fn player_movement(
             jumper.cooldown = false;
         }
 
-        if keyboard_input.pressed(KeyCode::Left) {
-            transform.translation.x -= 0.2;
-            if direction.0 != Direction::Left {
+        // Keys swim at full speed, while a stick swims as fast as it is pushed.
+        let horizontal = input.move_right - input.move_left;
+
+        if horizontal != 0. {
+            swim(&mut velocity, horizontal, TICK, &swim_settings);
+            if horizontal < 0. && direction.0 != Direction::Left {
                 direction.0 = Direction::Left;
                 sprite.flip_x = false;
-            }
-            update_move_anim(
-                &mut animation,
-                &mut animation_state,
-                &velocity,
-                &jumper,
-                &animations,
-            );
-        } else if keyboard_input.pressed(KeyCode::Right) {
-            transform.translation.x += 0.2;
-            if direction.0 != Direction::Right {
+            } else if horizontal > 0. && direction.0 != Direction::Right {
                 direction.0 = Direction::Right;
                 sprite.flip_x = true;
             }
@@ -273,6 +331,7 @@ fn player_movement(
                 &animations,
             );
         } else {
+            velocity.linvel.x *= (-swim_settings.drag * TICK).exp();
             if animation.0 != animations.idle && jumper.grounded {
                 animation.0 = animations.idle.clone();
                 animation_state.reset();
@@ -284,6 +343,14 @@ fn player_movement(
     }
 }
 
+/// Speeds up or slows down towards `direction` times the max speed, where `direction` is from
+/// -1 for full speed left to 1 for full speed right.
+fn swim(velocity: &mut Velocity, direction: f32, delta: f32, swim_settings: &SwimSettings) {
+    let target = direction * swim_settings.max_speed;
+    let step = swim_settings.acceleration * delta;
+    velocity.linvel.x += (target - velocity.linvel.x).clamp(-step, step);
+}
+
 fn update_move_anim(
     animation: &mut Animation,
     animation_state: &mut AnimationState,
@@ -333,15 +400,19 @@ fn jump_reset(
     }
 }
 
+#[allow(clippy::too_many_arguments)]
 fn collect_treasure(
     mut commands: Commands,
     player_query: Query<Entity, With<Player>>,
-    treasure_query: Query<Entity, With<Treasure>>,
+    treasure_query: Query<(Entity, &Treasure)>,
     message_query: Query<&Message>,
     mut collision_events: EventReader<CollisionEvent>,
     game_assets: Res<GameAssets>,
     time: Res<Time>,
     audio: Res<Audio>,
+    settings: Res<AccessibilitySettings>,
+    mut rng: ResMut<GameRng>,
+    mut collected: ResMut<CollectedTreasure>,
 ) {
     let messages = vec![
         "CONSUME".to_string(),
@@ -355,49 +426,29 @@ fn collect_treasure(
     for event in collision_events.iter() {
         if let CollisionEvent::Started(h1, h2, _flags) = event {
             let player = player_query.single();
-            let mut rng = rand::thread_rng();
-
-            for treasure in treasure_query.iter() {
-                if (h1 == &player && h2 == &treasure) || (h1 == &treasure && h2 == &player) {
-                    commands.entity(treasure).despawn_recursive();
-
-                    if message_query.iter().next().is_none()
-                        && rng.gen_range(0..100) < MSG_FREQUENCY
-                    {
-                        let message = messages.choose(&mut rng).unwrap();
-                        let mut node = commands.spawn_bundle(NodeBundle {
-                            style: Style {
-                                size: Size::new(Val::Percent(100.0), Val::Percent(100.0)),
-                                position_type: PositionType::Absolute,
-                                justify_content: JustifyContent::Center,
-                                align_items: AlignItems::FlexEnd,
-                                ..default()
-                            },
-                            ..default()
-                        });
-                        node.add_children(|parent| {
-                            parent
-                                .spawn_bundle(
-                                    // Create a TextBundle that has a Text with a single section.
-                                    TextBundle::from_section(
-                                        // Accepts a `String` or any type that converts into a `String`, such as `&str`
-                                        message,
-                                        TextStyle {
-                                            font: game_assets.ui_font.clone(),
-                                            font_size: 200.0,
-                                            color: Color::BLACK,
-                                        },
-                                    ) // Set the alignment of the Text
-                                    .with_text_alignment(TextAlignment::CENTER)
-                                    .with_style(Style {
-                                        align_self: AlignSelf::Center,
-                                        align_items: AlignItems::Center,
-                                        ..default()
-                                    }),
-                                )
-                                .id()
-                        });
-                        node.insert(Message(time.time_since_startup()));
+
+            for (entity, treasure) in treasure_query.iter() {
+                if (h1 == &player && h2 == &entity) || (h1 == &entity && h2 == &player) {
+                    commands.entity(entity).despawn_recursive();
+                    collected.0.push(treasure.cell);
+
+                    // Always draw both numbers, so how long messages stay up on screen cannot
+                    // change what is drawn for later treasure.
+                    let roll = rng.0.gen_range(0..100);
+                    let message = messages.choose(&mut rng.0).unwrap();
+                    if message_query.iter().next().is_none() && roll < MSG_FREQUENCY {
+                        let (mut node, duration) = match settings.message_style() {
+                            MessageStyle::Flash => (
+                                spawn_flash(&mut commands, &game_assets, message),
+                                MSG_THRESHOLD,
+                            ),
+                            MessageStyle::Toast => (
+                                spawn_toast(&mut commands, &game_assets, message),
+                                TOAST_DURATION,
+                            ),
+                        };
+                        node.insert(Message(time.time_since_startup() + duration))
+                            .insert(LevelEntity);
                         audio.play(game_assets.noise_sfx.clone());
                     } else {
                         audio.play(game_assets.coin_sfx.clone());
@@ -408,62 +459,117 @@ fn collect_treasure(
     }
 }
 
+/// Flashes a message in huge letters over the whole screen.
+fn spawn_flash<'w, 's, 'a>(
+    commands: &'a mut Commands<'w, 's>,
+    game_assets: &GameAssets,
+    message: &str,
+) -> EntityCommands<'w, 's, 'a> {
+    let mut node = commands.spawn_bundle(NodeBundle {
+        style: Style {
+            size: Size::new(Val::Percent(100.0), Val::Percent(100.0)),
+            position_type: PositionType::Absolute,
+            justify_content: JustifyContent::Center,
+            align_items: AlignItems::FlexEnd,
+            ..default()
+        },
+        ..default()
+    });
+    node.add_children(|parent| {
+        parent
+            .spawn_bundle(
+                // Create a TextBundle that has a Text with a single section.
+                TextBundle::from_section(
+                    // Accepts a `String` or any type that converts into a `String`, such as `&str`
+                    message,
+                    TextStyle {
+                        font: game_assets.ui_font.clone(),
+                        font_size: 200.0,
+                        color: Color::BLACK,
+                    },
+                ) // Set the alignment of the Text
+                .with_text_alignment(TextAlignment::CENTER)
+                .with_style(Style {
+                    align_self: AlignSelf::Center,
+                    align_items: AlignItems::Center,
+                    ..default()
+                }),
+            )
+            .id()
+    });
+    node
+}
+
+/// Shows a message in a small box at the bottom of the screen, leaving the rest of it alone.
+fn spawn_toast<'w, 's, 'a>(
+    commands: &'a mut Commands<'w, 's>,
+    game_assets: &GameAssets,
+    message: &str,
+) -> EntityCommands<'w, 's, 'a> {
+    let mut node = commands.spawn_bundle(NodeBundle {
+        style: Style {
+            size: Size::new(Val::Percent(100.0), Val::Percent(100.0)),
+            position_type: PositionType::Absolute,
+            justify_content: JustifyContent::Center,
+            align_items: AlignItems::FlexStart,
+            padding: UiRect {
+                bottom: Val::Px(50.0),
+                ..default()
+            },
+            ..default()
+        },
+        color: UiColor(Color::NONE),
+        ..default()
+    });
+    node.add_children(|parent| {
+        parent
+            .spawn_bundle(NodeBundle {
+                style: Style {
+                    padding: UiRect::all(Val::Px(20.0)),
+                    ..default()
+                },
+                color: UiColor(Color::rgba(0., 0., 0., 0.6)),
+                ..default()
+            })
+            .with_children(|parent| {
+                parent.spawn_bundle(TextBundle::from_section(
+                    message.replace('\n', " "),
+                    TextStyle {
+                        font: game_assets.ui_font.clone(),
+                        font_size: 40.0,
+                        color: Color::WHITE,
+                    },
+                ));
+            });
+    });
+    node
+}
+
 fn enter_portal(
-    mut commands: Commands,
+    mut state: ResMut<State<AppState>>,
     mut player_query: Query<(Entity, &mut Visibility), With<Player>>,
-    portal_query: Query<(Entity, &mut Portal)>,
-    success_query: Query<&Success>,
+    portal_query: Query<(Entity, &Portal)>,
     mut collision_events: EventReader<CollisionEvent>,
     game_assets: Res<GameAssets>,
     audio: Res<Audio>,
 ) {
-    if !success_query.iter().next().is_none() {
-        return;
-    }
-
     for event in collision_events.iter() {
         if let CollisionEvent::Started(h1, h2, _flags) = event {
             let (player_entity, mut player_visibility) = player_query.single_mut();
-            let (portal_entity, portal) = portal_query.single();
+            let (portal_entity, portal) = match portal_query.get_single() {
+                Ok(portal) => portal,
+                Err(_) => return,
+            };
 
             if portal.opened
                 && ((h1 == &player_entity && h2 == &portal_entity)
                     || (h1 == &portal_entity && h2 == &player_entity))
             {
-                let mut node = commands.spawn_bundle(NodeBundle {
-                    style: Style {
-                        size: Size::new(Val::Percent(100.0), Val::Percent(100.0)),
-                        position_type: PositionType::Absolute,
-                        justify_content: JustifyContent::Center,
-                        align_items: AlignItems::FlexEnd,
-                        ..default()
-                    },
-                    color: UiColor(Color::NONE),
-                    ..default()
-                });
-                node.add_children(|parent| {
-                    parent
-                        .spawn_bundle(
-                            TextBundle::from_section(
-                                "SUCCESS.\n",
-                                TextStyle {
-                                    font: game_assets.ui_font.clone(),
-                                    font_size: 200.0,
-                                    color: Color::WHITE,
-                                },
-                            )
-                            .with_text_alignment(TextAlignment::CENTER)
-                            .with_style(Style {
-                                align_self: AlignSelf::Center,
-                                align_items: AlignItems::Center,
-                                ..default()
-                            }),
-                        )
-                        .id()
-                });
-                node.insert(Success);
                 audio.play(game_assets.teleport_sfx.clone());
                 player_visibility.is_visible = false;
+                // Ignored if another transition was queued first, in which case it wins.
+                let _ = state.push(AppState::LevelComplete);
+                return;
             }
         }
     }
@@ -475,8 +581,78 @@ fn despawn_messages(
     time: Res<Time>,
 ) {
     for (entity, message) in message_query.iter_mut() {
-        if time.time_since_startup() - message.0 > MSG_THRESHOLD {
+        if time.time_since_startup() > message.0 {
             commands.entity(entity).despawn_recursive();
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::harness::{Harness, SWIM_RIGHT};
+
+    const LEVEL: &str = "1 1 1 1 1 1 1 1
+                         1 0 0 0 0 0 0 1
+                         1 0 0 0 0 0 0 1
+                         1 0 P 0 0 0 X 1
+                         1 1 1 1 1 1 1 1";
+
+    #[test]
+    fn swimming_up_leaves_the_floor() {
+        let mut harness = Harness::new(LEVEL);
+        harness.run(30, default());
+        let floor = harness.single::<&Transform, With<Player>>().translation.y;
+
+        harness.run(
+            10,
+            TickInput {
+                swim_up: true,
+                ..default()
+            },
+        );
+        assert!(harness.single::<&Transform, With<Player>>().translation.y > floor);
+    }
+
+    #[test]
+    fn swimming_sideways_turns_to_face_the_way_swum() {
+        let mut harness = Harness::new(LEVEL);
+        let start = harness.single::<&Transform, With<Player>>().translation.x;
+
+        harness.run(20, SWIM_RIGHT);
+        let (transform, sprite) =
+            harness.single::<(&Transform, &TextureAtlasSprite), With<Player>>();
+        assert!(transform.translation.x > start);
+        assert!(sprite.flip_x, "should face right");
+
+        harness.run(
+            40,
+            TickInput {
+                move_left: 1.,
+                ..default()
+            },
+        );
+        let (transform, sprite) =
+            harness.single::<(&Transform, &TextureAtlasSprite), With<Player>>();
+        assert!(transform.translation.x < start);
+        assert!(!sprite.flip_x, "should face left");
+    }
+
+    #[test]
+    fn half_pushed_stick_swims_slower() {
+        let mut full = Harness::new(LEVEL);
+        let mut half = Harness::new(LEVEL);
+        full.run(60, SWIM_RIGHT);
+        half.run(
+            60,
+            TickInput {
+                move_right: 0.5,
+                ..default()
+            },
+        );
+
+        let full_speed = full.single::<&Velocity, With<Player>>().linvel.x;
+        let half_speed = half.single::<&Velocity, With<Player>>().linvel.x;
+        assert!(half_speed > 0. && half_speed < full_speed);
+    }
+}
@@ -1,7 +1,9 @@
 use super::animation::{Animation, AnimationData, AnimationState};
 use super::app::AppState;
 use super::assets::GameAssets;
-use super::map::{Map, TILE_HEIGHT, TILE_WIDTH};
+use super::campaign::{CurrentLevel, LevelEntity};
+use super::map::{Map, MapEntity, MapReloaded, TILE_HEIGHT, TILE_WIDTH};
+use super::tick::{self, TickStage};
 use super::treasure::Treasure;
 use benimator::Frame;
 use bevy::prelude::*;
@@ -10,7 +12,7 @@ use std::time::Duration;
 
 #[derive(Component)]
 pub struct Portal {
-    pub opened: bool
+    pub opened: bool,
 }
 
 pub struct PortalAnimations {
@@ -23,7 +25,12 @@ pub struct PortalPlugin;
 impl Plugin for PortalPlugin {
     fn build(&self, app: &mut App) {
         app.add_system_set(SystemSet::on_enter(AppState::Game).with_system(setup_portal))
-            .add_system_set(SystemSet::on_update(AppState::Game).with_system(update_portal));
+            .add_system_set(SystemSet::on_update(AppState::Game).with_system(reload_portal));
+        tick::add_systems(
+            app,
+            TickStage::Gameplay,
+            SystemSet::new().with_system(update_portal),
+        );
     }
 }
 
@@ -31,9 +38,10 @@ fn setup_portal(
     mut commands: Commands,
     game_assets: Res<GameAssets>,
     maps: Res<Assets<Map>>,
+    level: Res<CurrentLevel>,
     mut animations: ResMut<Assets<AnimationData>>,
 ) {
-    let map = maps.get(&game_assets.map).unwrap();
+    let map = maps.get(&level.map).unwrap();
 
     let closed = AnimationData(benimator::Animation::from_frames(vec![Frame::new(
         0,
@@ -48,10 +56,39 @@ fn setup_portal(
     let opened_handle = animations.add(opened);
 
     commands.insert_resource(PortalAnimations {
-        opened: opened_handle.clone(),
+        opened: opened_handle,
         closed: closed_handle.clone(),
     });
 
+    spawn_portal(&mut commands, map, &game_assets, closed_handle);
+}
+
+fn reload_portal(
+    mut commands: Commands,
+    mut map_reloaded_events: EventReader<MapReloaded>,
+    game_assets: Res<GameAssets>,
+    maps: Res<Assets<Map>>,
+    level: Res<CurrentLevel>,
+    portal_animations: Res<PortalAnimations>,
+) {
+    if map_reloaded_events.iter().count() == 0 {
+        return;
+    }
+    let map = maps.get(&level.map).unwrap();
+    spawn_portal(
+        &mut commands,
+        map,
+        &game_assets,
+        portal_animations.closed.clone(),
+    );
+}
+
+fn spawn_portal(
+    commands: &mut Commands,
+    map: &Map,
+    game_assets: &GameAssets,
+    closed: Handle<AnimationData>,
+) {
     commands
         .spawn_bundle(SpriteSheetBundle {
             texture_atlas: game_assets.portal_atlas.clone(),
@@ -66,14 +103,14 @@ fn setup_portal(
             },
             ..default()
         })
-        .insert(Animation(closed_handle.clone()))
+        .insert(Animation(closed))
         .insert(AnimationState::default())
         .insert(Collider::cuboid(7., 7.))
         .insert(Sensor)
         .insert(ActiveEvents::COLLISION_EVENTS)
-        .insert(Portal {
-            opened: false
-        });
+        .insert(Portal { opened: false })
+        .insert(LevelEntity)
+        .insert(MapEntity);
 }
 
 fn update_portal(
@@ -83,11 +120,84 @@ fn update_portal(
     game_assets: Res<GameAssets>,
     audio: Res<Audio>,
 ) {
-    let (mut portal, mut animation, mut animation_state) = portal_query.single_mut();
-    if treasure_query.iter().next().is_none() && !portal.opened {
+    // The portal is briefly missing while the map is being reloaded.
+    let (mut portal, mut animation, mut animation_state) = match portal_query.get_single_mut() {
+        Ok(portal) => portal,
+        Err(_) => return,
+    };
+    let treasure_left = treasure_query.iter().next().is_some();
+    if !treasure_left && !portal.opened {
         animation.0 = portal_animations.opened.clone();
         animation_state.reset();
         portal.opened = true;
         audio.play(game_assets.portal_sfx.clone());
+    } else if treasure_left && portal.opened {
+        // Treasure the player collected can come back when they respawn.
+        animation.0 = portal_animations.closed.clone();
+        animation_state.reset();
+        portal.opened = false;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::harness::{Harness, SWIM_RIGHT};
+    use crate::player::Player;
+
+    fn is_open(world: &mut World) -> bool {
+        world.query::<&Portal>().single(world).opened
+    }
+
+    #[test]
+    fn collecting_all_treasure_opens_portal() {
+        let mut harness = Harness::new(
+            "1 1 1 1 1 1 1 1
+             1 0 0 0 0 0 0 1
+             1 P T 0 T 0 X 1
+             1 1 1 1 1 1 1 1",
+        );
+        harness.run_until(120, SWIM_RIGHT, |world| {
+            world.query::<&Treasure>().iter(world).count() == 1
+        });
+        harness.run(2, SWIM_RIGHT);
+        assert!(!is_open(&mut harness.app.world), "treasure is left");
+
+        harness.run_until(240, SWIM_RIGHT, is_open);
+        assert_eq!(
+            harness
+                .app
+                .world
+                .query::<&Treasure>()
+                .iter(&harness.app.world)
+                .count(),
+            0
+        );
+    }
+
+    #[test]
+    fn entering_open_portal_completes_level() {
+        let mut harness = Harness::new(
+            "1 1 1 1 1 1 1
+             1 0 0 0 0 0 1
+             1 P T 0 X 0 1
+             1 1 1 1 1 1 1",
+        );
+        harness.run_until(240, SWIM_RIGHT, Harness::level_complete);
+        assert!(!harness.single::<&Visibility, With<Player>>().is_visible);
+    }
+
+    #[test]
+    fn closed_portal_does_not_complete_level() {
+        let mut harness = Harness::new(
+            "1 1 1 1 1 1 1
+             1 0 0 0 0 0 1
+             1 P X 0 0 T 1
+             1 1 1 1 1 1 1",
+        );
+        harness.run(90, SWIM_RIGHT);
+
+        assert!(!is_open(&mut harness.app.world));
+        assert_eq!(harness.state(), &AppState::Game);
     }
 }
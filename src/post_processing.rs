@@ -0,0 +1,754 @@
+use super::accessibility::AccessibilitySettings;
+use super::assets::GameAssets;
+use super::camera::{GameCamera, ScaleMode, ScreenImage, ScreenQuad, GAME_HEIGHT, GAME_WIDTH};
+use super::campaign::CurrentLevel;
+use super::enemy::{Enemy, ENEMY_ATTACK_RADIUS};
+use super::map::{Map, MapLoadErrors, WaterSettings, TILE_HEIGHT};
+use super::player::Player;
+use super::restart::LevelOver;
+use bevy::{
+    asset::{AssetLoader, LoadContext, LoadedAsset},
+    ecs::system::SystemParam,
+    prelude::*,
+    reflect::TypeUuid,
+    render::{
+        camera::RenderTarget,
+        extract_resource::{ExtractResource, ExtractResourcePlugin},
+        render_resource::*,
+        renderer::RenderQueue,
+        texture::ImageSampler,
+        view::RenderLayers,
+        RenderApp, RenderStage,
+    },
+    sprite::{Material2d, Material2dPlugin, MaterialMesh2dBundle, RenderMaterials2d},
+    utils::BoxedFuture,
+};
+use std::{
+    marker::PhantomData,
+    str::{self, FromStr},
+};
+use thiserror::Error;
+use uniforms::WaterMaterialUniformData;
+
+/// Each pass needs a render layer of its own, and the screen camera already uses the last one.
+const MAX_PASSES: usize = 16;
+
+/// How close a piranha has to be for the water to start churning.
+const DANGER_RADIUS: f32 = ENEMY_ATTACK_RADIUS * 2.;
+/// How much the distortion grows with danger; at its peak, it is this many times as strong.
+const DANGER_DISTORTION: f32 = 3.;
+/// How quickly the water responds to danger; higher is snappier.
+const DANGER_SMOOTHING: f32 = 3.;
+
+/// Pressing F1 toggles the first pass in the chain, F2 the second, and so on. F10 and F11 are
+/// left for the display settings.
+const TOGGLE_KEYS: [KeyCode; 9] = [
+    KeyCode::F1,
+    KeyCode::F2,
+    KeyCode::F3,
+    KeyCode::F4,
+    KeyCode::F5,
+    KeyCode::F6,
+    KeyCode::F7,
+    KeyCode::F8,
+    KeyCode::F9,
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PassKind {
+    Water,
+    Crt,
+    Vignette,
+    ChromaticAberration,
+    DepthDarkening,
+}
+
+impl PassKind {
+    fn name(self) -> &'static str {
+        match self {
+            PassKind::Water => "water",
+            PassKind::Crt => "crt",
+            PassKind::Vignette => "vignette",
+            PassKind::ChromaticAberration => "chromatic_aberration",
+            PassKind::DepthDarkening => "depth_darkening",
+        }
+    }
+
+    /// The parameters a pass accepts, and their values if the chain does not set them.
+    fn parameters(self) -> &'static [(&'static str, f32)] {
+        match self {
+            PassKind::Water => &[],
+            PassKind::Crt => &[("scanlines", 0.25), ("curvature", 0.05)],
+            PassKind::Vignette => &[("strength", 0.5), ("radius", 0.75)],
+            PassKind::ChromaticAberration => &[("offset", 1.)],
+            PassKind::DepthDarkening => &[("strength", 0.5)],
+        }
+    }
+}
+
+impl FromStr for PassKind {
+    type Err = ();
+
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        [
+            PassKind::Water,
+            PassKind::Crt,
+            PassKind::Vignette,
+            PassKind::ChromaticAberration,
+            PassKind::DepthDarkening,
+        ]
+        .into_iter()
+        .find(|kind| kind.name() == name)
+        .ok_or(())
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct PassSettings {
+    pub kind: PassKind,
+    pub enabled: bool,
+    /// Values for each of `kind.parameters()`, in the same order.
+    values: Vec<f32>,
+}
+
+impl PassSettings {
+    fn parameter(&self, name: &str) -> f32 {
+        let index = self
+            .kind
+            .parameters()
+            .iter()
+            .position(|(parameter, _)| *parameter == name)
+            .unwrap();
+        self.values[index]
+    }
+}
+
+/// The post processing passes applied to the screen, in order, loaded from a `.chain` file.
+#[derive(Debug, TypeUuid)]
+#[uuid = "5a0e2c71-9b4d-4f83-a6e1-3c7d8b2f4e60"]
+pub struct PostProcessingChain {
+    pub passes: Vec<PassSettings>,
+}
+
+/// Reasons a post processing chain can be rejected. Lines are 1-based positions in the `.chain`
+/// file.
+#[derive(Debug, Error)]
+pub enum PostProcessingError {
+    #[error("post processing chain is not valid UTF-8: {0}")]
+    InvalidUtf8(#[from] str::Utf8Error),
+    #[error("line {line}: unknown pass `{pass}`")]
+    UnknownPass { line: usize, pass: String },
+    #[error("line {line}: expected a pass name after `off`")]
+    MissingPass { line: usize },
+    #[error("line {line}: `{pass}` has no parameter `{parameter}`")]
+    UnknownParameter {
+        line: usize,
+        pass: &'static str,
+        parameter: String,
+    },
+    #[error("line {line}: expected `<parameter>=<number>` but found `{found}`")]
+    InvalidParameter { line: usize, found: String },
+    #[error("line {line}: no more than {max} passes are supported")]
+    TooManyPasses { line: usize, max: usize },
+}
+
+impl FromStr for PostProcessingChain {
+    type Err = PostProcessingError;
+
+    fn from_str(chain_str: &str) -> Result<Self, Self::Err> {
+        let mut passes = vec![];
+        for (i, line) in chain_str.lines().enumerate() {
+            let line_number = i + 1;
+            let mut words = line.split_whitespace().peekable();
+            match words.peek() {
+                None => continue,
+                Some(word) if word.starts_with('#') => continue,
+                Some(_) => {}
+            }
+            if passes.len() == MAX_PASSES {
+                return Err(PostProcessingError::TooManyPasses {
+                    line: line_number,
+                    max: MAX_PASSES,
+                });
+            }
+
+            let enabled = words.next_if_eq(&"off").is_none();
+            let name = words
+                .next()
+                .ok_or(PostProcessingError::MissingPass { line: line_number })?;
+            let kind = name
+                .parse::<PassKind>()
+                .map_err(|_| PostProcessingError::UnknownPass {
+                    line: line_number,
+                    pass: name.to_string(),
+                })?;
+
+            let mut values = kind
+                .parameters()
+                .iter()
+                .map(|(_, value)| *value)
+                .collect::<Vec<f32>>();
+            for word in words {
+                let (parameter, value) = word
+                    .split_once('=')
+                    .and_then(|(parameter, value)| Some((parameter, value.parse::<f32>().ok()?)))
+                    .ok_or_else(|| PostProcessingError::InvalidParameter {
+                        line: line_number,
+                        found: word.to_string(),
+                    })?;
+                let index = kind
+                    .parameters()
+                    .iter()
+                    .position(|(name, _)| *name == parameter)
+                    .ok_or_else(|| PostProcessingError::UnknownParameter {
+                        line: line_number,
+                        pass: kind.name(),
+                        parameter: parameter.to_string(),
+                    })?;
+                values[index] = value;
+            }
+
+            passes.push(PassSettings {
+                kind,
+                enabled,
+                values,
+            });
+        }
+        Ok(PostProcessingChain { passes })
+    }
+}
+
+pub struct PostProcessingChainLoader {
+    errors: MapLoadErrors,
+}
+
+impl FromWorld for PostProcessingChainLoader {
+    fn from_world(world: &mut World) -> Self {
+        PostProcessingChainLoader {
+            errors: world
+                .get_resource_or_insert_with(MapLoadErrors::default)
+                .clone(),
+        }
+    }
+}
+
+impl AssetLoader for PostProcessingChainLoader {
+    fn load<'a>(
+        &'a self,
+        bytes: &'a [u8],
+        load_context: &'a mut LoadContext,
+    ) -> BoxedFuture<'a, Result<(), bevy::asset::Error>> {
+        Box::pin(async move {
+            match str::from_utf8(bytes)
+                .map_err(PostProcessingError::from)
+                .and_then(str::parse::<PostProcessingChain>)
+            {
+                Ok(chain) => {
+                    load_context.set_default_asset(LoadedAsset::new(chain));
+                    Ok(())
+                }
+                Err(err) => {
+                    self.errors
+                        .push(format!("{}: {}", load_context.path().display(), err));
+                    Err(err.into())
+                }
+            }
+        })
+    }
+
+    fn extensions(&self) -> &[&str] {
+        &["chain"]
+    }
+}
+
+/// Marks the quads and cameras making up the post processing chain, which are rebuilt whenever
+/// it changes.
+#[derive(Component)]
+struct PostProcessingPass;
+
+pub struct PostProcessingPlugin;
+
+impl Plugin for PostProcessingPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_asset::<PostProcessingChain>()
+            .init_asset_loader::<PostProcessingChainLoader>()
+            .add_plugin(Material2dPlugin::<WaterMaterial>::default())
+            .add_plugin(Material2dPlugin::<CrtMaterial>::default())
+            .add_plugin(Material2dPlugin::<VignetteMaterial>::default())
+            .add_plugin(Material2dPlugin::<ChromaticAberrationMaterial>::default())
+            .add_plugin(Material2dPlugin::<DepthDarkeningMaterial>::default())
+            .add_plugin(ExtractResourcePlugin::<ExtractedTime>::default())
+            .add_plugin(ExtractResourcePlugin::<WaterEffect>::default())
+            .init_resource::<WaterEffect>()
+            .add_system(build_chain)
+            .add_system(toggle_passes)
+            .add_system(update_depth)
+            .add_system(update_water);
+        app.sub_app_mut(RenderApp)
+            .add_system_to_stage(RenderStage::Prepare, prepare_water_material);
+    }
+}
+
+/// The materials of every kind of pass, bundled up to keep `build_chain` within the system
+/// parameter limit.
+#[derive(SystemParam)]
+struct PassMaterials<'w, 's> {
+    water: ResMut<'w, Assets<WaterMaterial>>,
+    crt: ResMut<'w, Assets<CrtMaterial>>,
+    vignette: ResMut<'w, Assets<VignetteMaterial>>,
+    chromatic_aberration: ResMut<'w, Assets<ChromaticAberrationMaterial>>,
+    depth_darkening: ResMut<'w, Assets<DepthDarkeningMaterial>>,
+    #[system_param(ignore)]
+    marker: PhantomData<&'s ()>,
+}
+
+#[allow(clippy::too_many_arguments)]
+fn build_chain(
+    mut commands: Commands,
+    mut chain_events: EventReader<AssetEvent<PostProcessingChain>>,
+    mut built: Local<bool>,
+    game_assets: Option<Res<GameAssets>>,
+    chains: Res<Assets<PostProcessingChain>>,
+    scale_mode: Res<ScaleMode>,
+    accessibility: Res<AccessibilitySettings>,
+    screen_image: Res<ScreenImage>,
+    mut images: ResMut<Assets<Image>>,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut materials: PassMaterials,
+    mut color_materials: ResMut<Assets<ColorMaterial>>,
+    screen_quad_query: Query<&Handle<ColorMaterial>, With<ScreenQuad>>,
+    pass_query: Query<Entity, With<PostProcessingPass>>,
+) {
+    let chain_changed = chain_events.iter().count() > 0;
+    if *built && !chain_changed && !scale_mode.is_changed() && !accessibility.is_changed() {
+        return;
+    }
+    let chain = match game_assets.and_then(|game_assets| chains.get(&game_assets.post_processing)) {
+        Some(chain) => chain,
+        None => return,
+    };
+    *built = true;
+
+    for entity in pass_query.iter() {
+        commands.entity(entity).despawn_recursive();
+    }
+
+    let size = Extent3d {
+        width: GAME_WIDTH,
+        height: GAME_HEIGHT,
+        ..default()
+    };
+    let quad = meshes.add(Mesh::from(shape::Quad::new(Vec2::new(
+        size.width as f32,
+        size.height as f32,
+    ))));
+
+    // Each pass draws the previous pass's image onto a quad, which its camera renders into an
+    // image of its own for the next pass.
+    let mut source = screen_image.0.clone();
+    for (index, pass) in chain.passes.iter().filter(|pass| pass.enabled).enumerate() {
+        let mut target = Image {
+            texture_descriptor: TextureDescriptor {
+                label: None,
+                size,
+                dimension: TextureDimension::D2,
+                format: TextureFormat::Bgra8UnormSrgb,
+                mip_level_count: 1,
+                sample_count: 1,
+                usage: TextureUsages::TEXTURE_BINDING
+                    | TextureUsages::COPY_DST
+                    | TextureUsages::RENDER_ATTACHMENT,
+            },
+            sampler_descriptor: ImageSampler::nearest(),
+            ..default()
+        };
+        target.resize(size);
+        let target = images.add(target);
+
+        let layer = RenderLayers::layer((RenderLayers::TOTAL_LAYERS - 2 - index) as u8);
+        let source_image = source.clone();
+        let quad = quad.clone();
+        match pass.kind {
+            PassKind::Water => spawn_pass(
+                &mut commands,
+                &mut materials.water,
+                WaterMaterial {
+                    source_image,
+                    uniforms: default(),
+                },
+                quad,
+                layer,
+            ),
+            PassKind::Crt => spawn_pass(
+                &mut commands,
+                &mut materials.crt,
+                CrtMaterial {
+                    source_image,
+                    scanlines: accessibility.contrast(pass.parameter("scanlines")),
+                    curvature: pass.parameter("curvature"),
+                },
+                quad,
+                layer,
+            ),
+            PassKind::Vignette => spawn_pass(
+                &mut commands,
+                &mut materials.vignette,
+                VignetteMaterial {
+                    source_image,
+                    strength: accessibility.contrast(pass.parameter("strength")),
+                    radius: pass.parameter("radius"),
+                },
+                quad,
+                layer,
+            ),
+            PassKind::ChromaticAberration => spawn_pass(
+                &mut commands,
+                &mut materials.chromatic_aberration,
+                ChromaticAberrationMaterial {
+                    source_image,
+                    offset: pass.parameter("offset"),
+                },
+                quad,
+                layer,
+            ),
+            PassKind::DepthDarkening => spawn_pass(
+                &mut commands,
+                &mut materials.depth_darkening,
+                DepthDarkeningMaterial {
+                    source_image,
+                    strength: accessibility.contrast(pass.parameter("strength")),
+                    depth: 0.,
+                },
+                quad,
+                layer,
+            ),
+        }
+
+        commands
+            .spawn_bundle(Camera2dBundle {
+                camera: Camera {
+                    target: RenderTarget::Image(target.clone()),
+                    priority: index as isize + 1,
+                    ..default()
+                },
+                transform: Transform::from_xyz(0., 0., 999.),
+                ..default()
+            })
+            .insert(UiCameraConfig { show_ui: false })
+            .insert(PostProcessingPass)
+            .insert(layer);
+
+        source = target;
+    }
+
+    // Only the final image is scaled up to the window, so only it is sampled smoothly.
+    images.get_mut(&source).unwrap().sampler_descriptor = match *scale_mode {
+        ScaleMode::PixelPerfect => ImageSampler::nearest(),
+        ScaleMode::Fit => ImageSampler::linear(),
+    };
+    if source != screen_image.0 {
+        images.get_mut(&screen_image.0).unwrap().sampler_descriptor = ImageSampler::nearest();
+    }
+    for material in screen_quad_query.iter() {
+        color_materials.get_mut(material).unwrap().texture = Some(source.clone());
+    }
+}
+
+/// Spawns the quad a pass draws its source image onto with `material`.
+fn spawn_pass<M: Material2d>(
+    commands: &mut Commands,
+    materials: &mut Assets<M>,
+    material: M,
+    quad: Handle<Mesh>,
+    layer: RenderLayers,
+) {
+    commands
+        .spawn_bundle(MaterialMesh2dBundle {
+            mesh: quad.into(),
+            material: materials.add(material),
+            ..default()
+        })
+        .insert(PostProcessingPass)
+        .insert(layer);
+}
+
+fn toggle_passes(
+    keyboard_input: Res<Input<KeyCode>>,
+    game_assets: Option<Res<GameAssets>>,
+    mut chains: ResMut<Assets<PostProcessingChain>>,
+) {
+    let game_assets = match game_assets {
+        Some(game_assets) => game_assets,
+        None => return,
+    };
+    for (index, key) in TOGGLE_KEYS.into_iter().enumerate() {
+        if !keyboard_input.just_pressed(key) {
+            continue;
+        }
+        // Only borrow the chain mutably when a pass is toggled, since that rebuilds it.
+        let pass_count = chains
+            .get(&game_assets.post_processing)
+            .map_or(0, |chain| chain.passes.len());
+        if index < pass_count {
+            let chain = chains.get_mut(&game_assets.post_processing).unwrap();
+            chain.passes[index].enabled = !chain.passes[index].enabled;
+        }
+    }
+}
+
+/// Darkens the screen further the deeper the camera is in the level.
+fn update_depth(
+    camera_query: Query<&Transform, With<GameCamera>>,
+    maps: Res<Assets<Map>>,
+    level: Option<Res<CurrentLevel>>,
+    material_query: Query<&Handle<DepthDarkeningMaterial>>,
+    mut materials: ResMut<Assets<DepthDarkeningMaterial>>,
+) {
+    let map = match level.and_then(|level| maps.get(&level.map)) {
+        Some(map) => map,
+        None => return,
+    };
+    let camera = camera_query.single();
+    let height = map.height as f32 * TILE_HEIGHT;
+    let depth = (1. - (camera.translation.y + TILE_HEIGHT / 2.) / height).clamp(0., 1.);
+
+    for handle in material_query.iter() {
+        // Changing a material rebuilds its bind group, so only do so when the depth changes.
+        let changed = materials
+            .get(handle)
+            .is_some_and(|material| (material.depth - depth).abs() >= 0.001);
+        if changed {
+            materials.get_mut(handle).unwrap().depth = depth;
+        }
+    }
+}
+
+/// Starts from the current level's water, and stirs it up as the player comes close to death, as
+/// far as the accessibility settings allow.
+#[allow(clippy::too_many_arguments)]
+fn update_water(
+    mut effect: ResMut<WaterEffect>,
+    mut danger: Local<f32>,
+    maps: Res<Assets<Map>>,
+    level: Option<Res<CurrentLevel>>,
+    player_query: Query<&Transform, With<Player>>,
+    enemy_query: Query<&Transform, With<Enemy>>,
+    level_over_query: Query<&LevelOver>,
+    accessibility: Res<AccessibilitySettings>,
+    time: Res<Time>,
+) {
+    let target = if level_over_query.iter().next().is_some() {
+        1.
+    } else {
+        player_query.iter().next().map_or(0., |player| {
+            enemy_query
+                .iter()
+                .map(|enemy| {
+                    let distance = enemy.translation.distance(player.translation);
+                    (1. - distance / DANGER_RADIUS).clamp(0., 1.)
+                })
+                .fold(0., f32::max)
+        })
+    };
+    *danger += (target - *danger) * (1. - (-DANGER_SMOOTHING * time.delta_seconds()).exp());
+
+    let mut settings = level
+        .and_then(|level| maps.get(&level.map))
+        .map_or_else(WaterSettings::default, |map| map.water);
+    settings.distortion *=
+        (1. + (DANGER_DISTORTION - 1.) * *danger) * accessibility.distortion.scale();
+    settings.saturation = 1. + accessibility.contrast(settings.saturation - 1.);
+    settings.tint_amount = accessibility.contrast(settings.tint_amount);
+    effect.0 = settings;
+}
+
+fn prepare_water_material(
+    materials: Res<RenderMaterials2d<WaterMaterial>>,
+    query: Query<&Handle<WaterMaterial>>,
+    time: Res<ExtractedTime>,
+    effect: Res<WaterEffect>,
+    render_queue: Res<RenderQueue>,
+) {
+    let settings = &effect.0;
+    let uniforms = WaterMaterialUniformData {
+        time_since_startup: time.time_since_startup,
+        scale: settings.scale,
+        distortion: settings.distortion,
+        motion1: settings.motion[0],
+        motion2: settings.motion[1],
+        tint: settings.tint,
+        tint_amount: settings.tint_amount,
+        saturation: settings.saturation,
+    };
+    for handle in &query {
+        if let Some(material) = materials.get(handle) {
+            for binding in material.bindings.iter() {
+                if let OwnedBindingResource::Buffer(cur_buffer) = binding {
+                    let mut buffer = encase::UniformBuffer::new(Vec::new());
+                    buffer.write(&uniforms).unwrap();
+                    render_queue.write_buffer(cur_buffer, 0, buffer.as_ref());
+                }
+            }
+        }
+    }
+}
+
+/// Ripples the screen as if seen through water.
+#[derive(AsBindGroup, TypeUuid, Clone)]
+#[uuid = "1c4b51b9-bf23-4fd5-8e80-febc028fdb4e"]
+struct WaterMaterial {
+    #[texture(0)]
+    #[sampler(1)]
+    source_image: Handle<Image>,
+    /// Rewritten every frame by `prepare_water_material`.
+    #[uniform(2)]
+    uniforms: WaterMaterialUniformData,
+}
+
+impl Material2d for WaterMaterial {
+    fn fragment_shader() -> ShaderRef {
+        "shaders/water.wgsl".into()
+    }
+}
+
+/// Kept in a module of its own, since the `ShaderType` derive generates a layout check for each
+/// field that is never called.
+#[allow(dead_code)]
+mod uniforms {
+    use bevy::{prelude::*, render::render_resource::ShaderType};
+
+    #[derive(Clone, Default, ShaderType)]
+    pub struct WaterMaterialUniformData {
+        pub time_since_startup: f32,
+        pub scale: f32,
+        pub distortion: f32,
+        pub motion1: Vec2,
+        pub motion2: Vec2,
+        pub tint: Vec4,
+        pub tint_amount: f32,
+        pub saturation: f32,
+    }
+}
+
+/// The water settings the water pass is currently drawn with.
+#[derive(Clone, Default)]
+struct WaterEffect(WaterSettings);
+
+impl ExtractResource for WaterEffect {
+    type Source = WaterEffect;
+
+    fn extract_resource(effect: &Self::Source) -> Self {
+        effect.clone()
+    }
+}
+
+/// Curves the screen and darkens every other line, like an old CRT television.
+#[derive(AsBindGroup, TypeUuid, Clone)]
+#[uuid = "b7e4d1a2-3c58-4f96-8a0b-6d2e9f1c7a35"]
+struct CrtMaterial {
+    #[texture(0)]
+    #[sampler(1)]
+    source_image: Handle<Image>,
+    /// How much darker every other line is, from 0 to 1.
+    #[uniform(2)]
+    scanlines: f32,
+    #[uniform(3)]
+    curvature: f32,
+}
+
+impl Material2d for CrtMaterial {
+    fn fragment_shader() -> ShaderRef {
+        "shaders/crt.wgsl".into()
+    }
+}
+
+/// Darkens the edges of the screen.
+#[derive(AsBindGroup, TypeUuid, Clone)]
+#[uuid = "2f9c6e84-d1b3-47a0-95e7-8a4b0c3d6f12"]
+struct VignetteMaterial {
+    #[texture(0)]
+    #[sampler(1)]
+    source_image: Handle<Image>,
+    #[uniform(2)]
+    strength: f32,
+    /// How far from the center the darkening starts, where the corners are 1.
+    #[uniform(3)]
+    radius: f32,
+}
+
+impl Material2d for VignetteMaterial {
+    fn fragment_shader() -> ShaderRef {
+        "shaders/vignette.wgsl".into()
+    }
+}
+
+/// Splits the red and blue channels apart towards the edges of the screen.
+#[derive(AsBindGroup, TypeUuid, Clone)]
+#[uuid = "c3a81f5e-7b20-4d69-b4e3-1f0d9a6c2e87"]
+struct ChromaticAberrationMaterial {
+    #[texture(0)]
+    #[sampler(1)]
+    source_image: Handle<Image>,
+    /// How far apart the channels are at the edges of the screen, in pixels.
+    #[uniform(2)]
+    offset: f32,
+}
+
+impl Material2d for ChromaticAberrationMaterial {
+    fn fragment_shader() -> ShaderRef {
+        "shaders/chromatic_aberration.wgsl".into()
+    }
+}
+
+/// Darkens the screen the deeper the player is in the level.
+#[derive(AsBindGroup, TypeUuid, Clone)]
+#[uuid = "e86d0b37-4a1f-4c2e-9d75-b0c3a8e1f496"]
+struct DepthDarkeningMaterial {
+    #[texture(0)]
+    #[sampler(1)]
+    source_image: Handle<Image>,
+    #[uniform(2)]
+    strength: f32,
+    /// How deep the view is in the level, from 0 at the top to 1 at the bottom.
+    #[uniform(3)]
+    depth: f32,
+}
+
+impl Material2d for DepthDarkeningMaterial {
+    fn fragment_shader() -> ShaderRef {
+        "shaders/depth_darkening.wgsl".into()
+    }
+}
+
+struct ExtractedTime {
+    time_since_startup: f32,
+}
+
+impl ExtractResource for ExtractedTime {
+    type Source = Time;
+
+    fn extract_resource(time: &Self::Source) -> Self {
+        ExtractedTime {
+            time_since_startup: time.seconds_since_startup() as f32,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse_error(chain: &str) -> PostProcessingError {
+        chain.parse::<PostProcessingChain>().unwrap_err()
+    }
+
+    #[test]
+    fn off_without_a_pass_reports_its_line() {
+        let error = parse_error("water\noff");
+        assert!(
+            matches!(error, PostProcessingError::MissingPass { line: 2 }),
+            "{:?}",
+            error
+        );
+    }
+}
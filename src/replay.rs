@@ -0,0 +1,189 @@
+use super::app::AppState;
+use super::campaign::CurrentLevel;
+use super::input::{Action, Actions};
+use super::settings;
+use super::tick::{self, TickStage};
+use bevy::prelude::*;
+use bevy_rapier2d::prelude::*;
+use rand::{rngs::StdRng, SeedableRng};
+use serde::{Deserialize, Serialize};
+
+const REPLAY_FILE: &str = "replay.json";
+
+/// The input that moves the player during one tick, whether from the player or a recording.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct TickInput {
+    /// How strongly the player swims left and right, from 0 to 1.
+    pub move_left: f32,
+    pub move_right: f32,
+    pub swim_up: bool,
+}
+
+impl TickInput {
+    /// The input the player is giving with the keyboard and gamepads right now.
+    pub fn read(actions: &Actions) -> Self {
+        TickInput {
+            move_left: actions.value(Action::MoveLeft),
+            move_right: actions.value(Action::MoveRight),
+            swim_up: actions.pressed(Action::SwimUp),
+        }
+    }
+}
+
+/// Randomness that can change how a run goes, seeded when each level starts so a replay draws
+/// the same numbers as the run it recorded.
+pub struct GameRng(pub StdRng);
+
+impl Default for GameRng {
+    fn default() -> Self {
+        GameRng(StdRng::seed_from_u64(0))
+    }
+}
+
+/// Consecutive ticks with the same input.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct InputRun {
+    ticks: u32,
+    input: TickInput,
+}
+
+/// Everything needed to play a run of a level again: which level, the seed, and the input for
+/// every tick.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Recording {
+    pub level: usize,
+    seed: u64,
+    runs: Vec<InputRun>,
+}
+
+impl Recording {
+    fn new(level: usize) -> Self {
+        Recording {
+            level,
+            seed: rand::random(),
+            runs: Vec::new(),
+        }
+    }
+
+    /// The last run saved, if there is one that can be read.
+    pub fn load() -> Option<Self> {
+        settings::load::<Option<Recording>>(REPLAY_FILE)
+    }
+
+    fn push(&mut self, input: TickInput) {
+        match self.runs.last_mut() {
+            Some(run) if run.input == input => run.ticks += 1,
+            _ => self.runs.push(InputRun { ticks: 1, input }),
+        }
+    }
+}
+
+/// Where each tick's input comes from.
+pub enum Replay {
+    /// The player, whose inputs are recorded.
+    Recording(Recording),
+    /// A recording, played back `tick` ticks into its `run`th run of inputs.
+    Playing {
+        recording: Recording,
+        run: usize,
+        tick: u32,
+    },
+}
+
+impl Default for Replay {
+    fn default() -> Self {
+        Replay::Recording(default())
+    }
+}
+
+impl Replay {
+    /// Plays a recording back from the start the next time its level starts.
+    pub fn play(recording: Recording) -> Self {
+        Replay::Playing {
+            recording,
+            run: 0,
+            tick: 0,
+        }
+    }
+}
+
+pub struct ReplayPlugin;
+
+impl Plugin for ReplayPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<TickInput>()
+            .init_resource::<GameRng>()
+            .init_resource::<Replay>()
+            .add_system_set(SystemSet::on_enter(AppState::Game).with_system(start_run))
+            .add_system_set(SystemSet::on_enter(AppState::GameOver).with_system(save_recording))
+            .add_system_set(
+                SystemSet::on_enter(AppState::LevelComplete).with_system(save_recording),
+            );
+        tick::add_systems(
+            app,
+            TickStage::Input,
+            SystemSet::new().with_system(read_input),
+        );
+    }
+}
+
+fn start_run(
+    mut replay: ResMut<Replay>,
+    mut rng: ResMut<GameRng>,
+    mut rapier_context: ResMut<RapierContext>,
+    level: Res<CurrentLevel>,
+) {
+    // Watch a recording just chosen from its start, otherwise record this run.
+    let seed = match &*replay {
+        Replay::Playing {
+            recording,
+            run: 0,
+            tick: 0,
+        } if recording.level == level.index => recording.seed,
+        _ => {
+            let recording = Recording::new(level.index);
+            let seed = recording.seed;
+            *replay = Replay::Recording(recording);
+            seed
+        }
+    };
+    rng.0 = StdRng::seed_from_u64(seed);
+
+    // Physics starts afresh too, so nothing left over from earlier levels changes how this one
+    // plays out. The game uses Rapier's default physics scale.
+    *rapier_context = RapierContext::default();
+}
+
+fn read_input(actions: Actions, mut replay: ResMut<Replay>, mut input: ResMut<TickInput>) {
+    *input = match &mut *replay {
+        Replay::Recording(recording) => {
+            let input = TickInput::read(&actions);
+            recording.push(input);
+            input
+        }
+        Replay::Playing {
+            recording,
+            run,
+            tick,
+        } => match recording.runs.get(*run) {
+            Some(current) => {
+                *tick += 1;
+                if *tick == current.ticks {
+                    *run += 1;
+                    *tick = 0;
+                }
+                current.input
+            }
+            // The player stops once the recording runs out.
+            None => default(),
+        },
+    };
+}
+
+/// Saves the run once it ends, so it can be watched again.
+fn save_recording(replay: Res<Replay>) {
+    if let Replay::Recording(recording) = &*replay {
+        settings::save(REPLAY_FILE, recording);
+    }
+}
@@ -0,0 +1,51 @@
+use super::app::AppState;
+use super::input::{Action, Actions};
+use bevy::prelude::*;
+use std::time::Duration;
+
+// How long to show how the player died before offering to restart
+const PROMPT_DELAY: Duration = Duration::from_secs(3);
+
+/// Marks the message shown when the player dies, allowing the level to be restarted.
+#[derive(Component)]
+pub struct LevelOver {
+    pub since: Duration,
+}
+
+pub struct RestartPlugin;
+
+impl Plugin for RestartPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_system_set(
+            SystemSet::on_update(AppState::Game)
+                .with_system(show_game_over)
+                .with_system(restart_level),
+        )
+        .add_system_set(SystemSet::on_update(AppState::GameOver).with_system(restart_level));
+    }
+}
+
+fn show_game_over(
+    mut state: ResMut<State<AppState>>,
+    level_over_query: Query<&LevelOver>,
+    time: Res<Time>,
+) {
+    for level_over in level_over_query.iter() {
+        if time.time_since_startup() - level_over.since > PROMPT_DELAY {
+            // Ignored if the player restarted or paused on this same frame.
+            let _ = state.push(AppState::GameOver);
+            return;
+        }
+    }
+}
+
+fn restart_level(
+    mut state: ResMut<State<AppState>>,
+    mut actions: Actions,
+    level_over_query: Query<&LevelOver>,
+) {
+    if level_over_query.iter().next().is_some() && actions.clear_just_pressed(Action::Restart) {
+        // Ignored if the game over menu acted on this same frame, in which case it wins.
+        let _ = state.replace(AppState::LoadingLevel);
+    }
+}
@@ -0,0 +1,33 @@
+use bevy::{asset::FileAssetIo, prelude::*};
+use serde::{de::DeserializeOwned, Serialize};
+use std::fs;
+
+/// Reads settings saved by `save`, falling back to the defaults if there are none or they cannot
+/// be read.
+pub fn load<T: DeserializeOwned + Default>(file: &str) -> T {
+    let path = FileAssetIo::get_base_path().join(file);
+    let json = match fs::read_to_string(&path) {
+        Ok(json) => json,
+        Err(_) => return default(),
+    };
+    serde_json::from_str(&json).unwrap_or_else(|err| {
+        warn!("ignoring {}: {}", path.display(), err);
+        default()
+    })
+}
+
+/// Writes settings next to the game as JSON, creating the directory `file` is in if needed.
+/// Failures are logged since there is nowhere better to report them.
+pub fn save<T: Serialize>(file: &str, settings: &T) {
+    let path = FileAssetIo::get_base_path().join(file);
+    let result = serde_json::to_string_pretty(settings)
+        .map_err(|err| err.to_string())
+        .and_then(|json| {
+            fs::create_dir_all(path.parent().unwrap())
+                .and_then(|()| fs::write(&path, json))
+                .map_err(|err| err.to_string())
+        });
+    if let Err(err) = result {
+        error!("could not save {}: {}", path.display(), err);
+    }
+}
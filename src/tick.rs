@@ -0,0 +1,165 @@
+use super::app::AppState;
+use bevy::{ecs::schedule::ShouldRun, prelude::*, time::FixedTimestep};
+use bevy_rapier2d::prelude::*;
+
+/// Length of a gameplay tick in seconds. Gameplay and physics advance by whole ticks however long
+/// frames take, so the same inputs always play out the same way.
+pub const TICK: f32 = 1. / 60.;
+
+#[derive(StageLabel)]
+struct FixedUpdate;
+
+#[derive(StageLabel)]
+struct PlaceColliders;
+
+/// The stages run every tick, followed by the physics step.
+#[derive(StageLabel)]
+pub enum TickStage {
+    /// Decides the input for the tick and clears what was tracked during the last one.
+    Input,
+    /// Moves the player and enemies and resolves what they ran into.
+    Gameplay,
+}
+
+/// Runs gameplay and physics on a fixed timestep. This sets up Rapier's stages itself, so
+/// `RapierPhysicsPlugin` must be added with its default system setup turned off.
+pub struct TickPlugin;
+
+impl Plugin for TickPlugin {
+    fn build(&self, app: &mut App) {
+        // Physics takes exactly one tick's step each time it runs, rather than following the clock.
+        app.insert_resource(RapierConfiguration {
+            timestep_mode: TimestepMode::Fixed {
+                dt: TICK,
+                substeps: 1,
+            },
+            ..default()
+        })
+        .add_stage_after(
+            CoreStage::Update,
+            FixedUpdate,
+            Schedule::default()
+                .with_run_criteria(FixedTimestep::step(TICK as f64))
+                .with_stage(TickStage::Input, SystemStage::parallel())
+                .with_stage(TickStage::Gameplay, SystemStage::parallel())
+                .with_stage(
+                    PhysicsStages::SyncBackend,
+                    physics_stage(PhysicsStages::SyncBackend),
+                )
+                // Rapier creates colliders without bodies where their entity was when it last
+                // moved, so one spawned on a frame without a tick would be left at the origin.
+                // Moving them again once they exist puts walls and sensors where they belong.
+                .with_stage(
+                    PlaceColliders,
+                    SystemStage::parallel()
+                        .with_system(bevy_rapier2d::plugin::systems::apply_collider_user_changes),
+                )
+                .with_stage(
+                    PhysicsStages::StepSimulation,
+                    physics_stage(PhysicsStages::StepSimulation),
+                )
+                .with_stage(
+                    PhysicsStages::Writeback,
+                    physics_stage(PhysicsStages::Writeback),
+                ),
+        )
+        // Despawned bodies are cleaned up every frame, as Rapier normally does.
+        .add_stage_before(
+            CoreStage::Last,
+            PhysicsStages::DetectDespawn,
+            physics_stage(PhysicsStages::DetectDespawn),
+        );
+    }
+}
+
+fn physics_stage(stage: PhysicsStages) -> SystemStage {
+    SystemStage::parallel().with_system_set(RapierPhysicsPlugin::<NoUserData>::get_systems(stage))
+}
+
+/// Adds systems to run every tick while a level is being played, but not while it is paused or
+/// over.
+pub fn add_systems(app: &mut App, stage: TickStage, systems: SystemSet) {
+    app.stage(FixedUpdate, |schedule: &mut Schedule| {
+        schedule.add_system_set_to_stage(stage, systems.with_run_criteria(playing))
+    });
+}
+
+fn playing(state: Res<State<AppState>>) -> ShouldRun {
+    if state.current() == &AppState::Game {
+        ShouldRun::Yes
+    } else {
+        ShouldRun::No
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::harness::{Harness, SWIM_RIGHT};
+    use crate::player::Player;
+    use crate::replay::TickInput;
+    use bevy::prelude::*;
+    use bevy_rapier2d::prelude::*;
+
+    #[test]
+    fn colliders_spawned_between_ticks_are_placed() {
+        // The harness spawns the level on a frame without a tick.
+        let mut harness = Harness::new(
+            "1 1 1 1 1 1
+             1 0 0 0 0 1
+             1 P T 0 X 1
+             1 1 1 1 1 1",
+        );
+        harness.run(1, default());
+
+        let world = &mut harness.app.world;
+        let placed = world
+            .query_filtered::<(Entity, &GlobalTransform), (With<Collider>, Without<RigidBody>)>()
+            .iter(world)
+            .map(|(entity, transform)| (entity, transform.translation().truncate()))
+            .collect::<Vec<(Entity, Vec2)>>();
+        assert!(placed.len() > 4, "walls, treasure and the portal");
+        let context = world.resource::<RapierContext>();
+        for (entity, expected) in placed {
+            let handle = context.entity2collider()[&entity];
+            let position = context.colliders[handle].translation();
+            assert_eq!(Vec2::new(position.x, position.y), expected);
+        }
+    }
+
+    #[test]
+    fn same_inputs_play_out_the_same() {
+        let level = "1 1 1 1 1 1 1 1 1 1
+                     1 0 0 0 0 0 0 0 0 1
+                     1 0 0 0 0 0 0 0 0 1
+                     1 P 0 T 0 0 E 0 X 1
+                     1 1 1 1 1 1 1 1 1 1";
+        let inputs = [
+            (20, SWIM_RIGHT),
+            (
+                5,
+                TickInput {
+                    move_right: 0.7,
+                    swim_up: true,
+                    ..default()
+                },
+            ),
+            (30, TickInput::default()),
+            (
+                25,
+                TickInput {
+                    move_left: 1.,
+                    ..default()
+                },
+            ),
+        ];
+
+        let positions = [(); 2].map(|()| {
+            let mut harness = Harness::new(level);
+            for (ticks, input) in inputs {
+                harness.run(ticks, input);
+            }
+            harness.single::<&Transform, With<Player>>().translation
+        });
+        assert_eq!(positions[0], positions[1]);
+    }
+}
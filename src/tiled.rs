@@ -0,0 +1,635 @@
+use super::map::{file_hash, Map, MapError, MapLoadErrors, Tile};
+use bevy::{
+    asset::{AssetLoader, LoadContext, LoadedAsset},
+    prelude::*,
+    utils::BoxedFuture,
+};
+use serde::Deserialize;
+use std::{ffi::OsStr, str};
+use xml::{attribute::OwnedAttribute, reader::XmlEvent, EventReader};
+
+// Tiled stores flip and rotation flags in the high bits of each global tile id.
+const GID_MASK: u32 = 0x0fff_ffff;
+
+/// Loads maps made in the [Tiled](https://www.mapeditor.org/) editor, saved as either XML
+/// (`.tmx`) or JSON (`.tmj`).
+///
+/// Any tile on a tile layer becomes a wall. Objects are placed by their type (class in newer
+/// versions of Tiled), or by their name if they have none, which must be one of `player`,
+/// `portal`, `treasure`, `enemy`, `checkpoint` or `air`. An `air` object turns the cell it is in
+/// into an air pocket.
+pub struct TiledMapLoader {
+    errors: MapLoadErrors,
+}
+
+impl FromWorld for TiledMapLoader {
+    fn from_world(world: &mut World) -> Self {
+        TiledMapLoader {
+            errors: world
+                .get_resource_or_insert_with(MapLoadErrors::default)
+                .clone(),
+        }
+    }
+}
+
+impl AssetLoader for TiledMapLoader {
+    fn load<'a>(
+        &'a self,
+        bytes: &'a [u8],
+        load_context: &'a mut LoadContext,
+    ) -> BoxedFuture<'a, Result<(), bevy::asset::Error>> {
+        Box::pin(async move {
+            let is_json = load_context.path().extension() == Some(OsStr::new("tmj"));
+            let tiled_map = if is_json {
+                parse_tmj(bytes)
+            } else {
+                parse_tmx(bytes)
+            };
+
+            match tiled_map.and_then(TiledMap::into_map) {
+                Ok(mut map) => {
+                    map.file_hash = file_hash(bytes);
+                    load_context.set_default_asset(LoadedAsset::new(map));
+                    Ok(())
+                }
+                Err(err) => {
+                    self.errors
+                        .push(format!("{}: {}", load_context.path().display(), err));
+                    Err(err.into())
+                }
+            }
+        })
+    }
+
+    fn extensions(&self) -> &[&str] {
+        &["tmx", "tmj"]
+    }
+}
+
+/// The parts of a Tiled map used by the game, common to both file formats.
+#[derive(Default)]
+struct TiledMap {
+    width: usize,
+    height: usize,
+    tile_width: f32,
+    tile_height: f32,
+    /// Global tile ids of each tile layer, in rows from the top of the map.
+    layers: Vec<Vec<u32>>,
+    objects: Vec<TiledObject>,
+    /// Custom properties of the map itself, by name.
+    properties: Vec<(String, String)>,
+}
+
+#[derive(Default)]
+struct TiledObject {
+    id: u32,
+    kind: String,
+    x: f32,
+    y: f32,
+    width: f32,
+    height: f32,
+    /// Set for tile objects, which Tiled positions by their bottom-left corner.
+    gid: Option<u32>,
+}
+
+impl TiledMap {
+    fn into_map(self) -> Result<Map, MapError> {
+        if self.width == 0 || self.height == 0 {
+            return Err(MapError::Empty);
+        }
+
+        let mut map = Map {
+            width: self.width,
+            height: self.height,
+            tiles: vec![vec![Tile::Empty; self.width]; self.height],
+            player_spawn: (0, 0),
+            portal_spawn: (0, 0),
+            treasures: vec![],
+            enemies: vec![],
+            checkpoints: vec![],
+            water: default(),
+            // Filled in by the loader, which has the file itself.
+            file_hash: 0,
+        };
+
+        // Other properties are left for Tiled and any other tools.
+        for (name, value) in self.properties.iter() {
+            if let Some(setting) = name.strip_prefix("water.") {
+                map.water
+                    .set(setting, value)
+                    .ok_or_else(|| MapError::Tiled(format!("invalid map property `{}`", name)))?;
+            }
+        }
+
+        for layer in self.layers.iter() {
+            if layer.len() != self.width * self.height {
+                return Err(MapError::Tiled(format!(
+                    "tile layer has {} tiles but the map is {}x{}",
+                    layer.len(),
+                    self.width,
+                    self.height
+                )));
+            }
+            for (index, gid) in layer.iter().enumerate() {
+                if gid & GID_MASK != 0 {
+                    let row = self.height - 1 - index / self.width;
+                    map.tiles[row][index % self.width] = Tile::Wall;
+                }
+            }
+        }
+
+        let mut player_spawn = None;
+        let mut portal_spawn = None;
+        for object in self.objects.iter() {
+            let kind = object.kind.to_lowercase();
+            let center_x = object.x + object.width / 2.;
+            let center_y = match object.gid {
+                Some(_) => object.y - object.height / 2.,
+                None => object.y + object.height / 2.,
+            };
+            let column = (center_x / self.tile_width).floor();
+            let row_from_top = (center_y / self.tile_height).floor();
+            if column < 0.
+                || row_from_top < 0.
+                || column >= self.width as f32
+                || row_from_top >= self.height as f32
+            {
+                return Err(MapError::ObjectOutOfBounds {
+                    id: object.id,
+                    kind: object.kind.clone(),
+                });
+            }
+            let position = (
+                column as u32,
+                (self.height - 1) as u32 - row_from_top as u32,
+            );
+
+            let spawn = match kind.as_str() {
+                "player" => &mut player_spawn,
+                "portal" => &mut portal_spawn,
+                "treasure" => {
+                    map.treasures.push(position);
+                    continue;
+                }
+                "enemy" => {
+                    map.enemies.push(position);
+                    continue;
+                }
+                "checkpoint" => {
+                    map.checkpoints.push(position);
+                    continue;
+                }
+                "air" => {
+                    map.tiles[position.1 as usize][position.0 as usize] = Tile::Air;
+                    continue;
+                }
+                _ => {
+                    return Err(MapError::UnknownObject {
+                        id: object.id,
+                        kind: object.kind.clone(),
+                    })
+                }
+            };
+            if spawn.replace(position).is_some() {
+                return Err(MapError::DuplicateObject {
+                    id: object.id,
+                    kind: object.kind.clone(),
+                });
+            }
+        }
+
+        map.player_spawn = player_spawn.ok_or(MapError::MissingPlayerSpawn)?;
+        map.portal_spawn = portal_spawn.ok_or(MapError::MissingPortal)?;
+        Ok(map)
+    }
+}
+
+fn decode_layer_data(
+    data: &str,
+    encoding: Option<&str>,
+    compression: Option<&str>,
+) -> Result<Vec<u32>, MapError> {
+    if let Some(compression) = compression.filter(|compression| !compression.is_empty()) {
+        return Err(MapError::Tiled(format!(
+            "{} compressed layers are not supported",
+            compression
+        )));
+    }
+
+    match encoding {
+        Some("csv") => data
+            .split(',')
+            .map(str::trim)
+            .filter(|gid| !gid.is_empty())
+            .map(|gid| {
+                gid.parse()
+                    .map_err(|_| MapError::Tiled(format!("invalid tile id `{}`", gid)))
+            })
+            .collect(),
+        Some("base64") => {
+            let bytes = base64::decode(data.trim())
+                .map_err(|err| MapError::Tiled(format!("invalid base64 layer data: {}", err)))?;
+            Ok(bytes
+                .chunks_exact(4)
+                .map(|gid| u32::from_le_bytes([gid[0], gid[1], gid[2], gid[3]]))
+                .collect())
+        }
+        other => Err(MapError::Tiled(format!(
+            "unsupported layer encoding `{}`",
+            other.unwrap_or("xml")
+        ))),
+    }
+}
+
+#[derive(Deserialize)]
+struct TmjMap {
+    width: usize,
+    height: usize,
+    tilewidth: f32,
+    tileheight: f32,
+    #[serde(default)]
+    infinite: bool,
+    layers: Vec<TmjLayer>,
+    #[serde(default)]
+    properties: Vec<TmjProperty>,
+}
+
+#[derive(Deserialize)]
+struct TmjProperty {
+    name: String,
+    value: serde_json::Value,
+}
+
+#[derive(Deserialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+enum TmjLayer {
+    TileLayer {
+        data: TmjLayerData,
+        encoding: Option<String>,
+        compression: Option<String>,
+    },
+    ObjectGroup {
+        objects: Vec<TmjObject>,
+    },
+    Group {
+        layers: Vec<TmjLayer>,
+    },
+    #[serde(other)]
+    Other,
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum TmjLayerData {
+    Array(Vec<u32>),
+    Encoded(String),
+}
+
+#[derive(Deserialize)]
+struct TmjObject {
+    id: u32,
+    #[serde(default)]
+    name: String,
+    #[serde(default, rename = "type")]
+    kind: String,
+    #[serde(default)]
+    class: String,
+    x: f32,
+    y: f32,
+    #[serde(default)]
+    width: f32,
+    #[serde(default)]
+    height: f32,
+    gid: Option<u32>,
+}
+
+fn parse_tmj(bytes: &[u8]) -> Result<TiledMap, MapError> {
+    let tmj: TmjMap =
+        serde_json::from_slice(bytes).map_err(|err| MapError::Tiled(err.to_string()))?;
+    if tmj.infinite {
+        return Err(MapError::Tiled(
+            "infinite maps are not supported".to_string(),
+        ));
+    }
+
+    let mut tiled_map = TiledMap {
+        width: tmj.width,
+        height: tmj.height,
+        tile_width: tmj.tilewidth,
+        tile_height: tmj.tileheight,
+        properties: tmj
+            .properties
+            .into_iter()
+            .map(|property| {
+                let value = match property.value {
+                    serde_json::Value::String(value) => value,
+                    value => value.to_string(),
+                };
+                (property.name, value)
+            })
+            .collect(),
+        ..default()
+    };
+    add_tmj_layers(&mut tiled_map, tmj.layers)?;
+    Ok(tiled_map)
+}
+
+fn add_tmj_layers(tiled_map: &mut TiledMap, layers: Vec<TmjLayer>) -> Result<(), MapError> {
+    for layer in layers {
+        match layer {
+            TmjLayer::TileLayer {
+                data,
+                encoding,
+                compression,
+            } => {
+                let gids = match data {
+                    TmjLayerData::Array(gids) => gids,
+                    TmjLayerData::Encoded(data) => {
+                        decode_layer_data(&data, encoding.as_deref(), compression.as_deref())?
+                    }
+                };
+                tiled_map.layers.push(gids);
+            }
+            TmjLayer::ObjectGroup { objects } => {
+                tiled_map
+                    .objects
+                    .extend(objects.into_iter().map(|object| TiledObject {
+                        id: object.id,
+                        kind: object_kind(object.kind, object.class, object.name),
+                        x: object.x,
+                        y: object.y,
+                        width: object.width,
+                        height: object.height,
+                        gid: object.gid,
+                    }));
+            }
+            TmjLayer::Group { layers } => add_tmj_layers(tiled_map, layers)?,
+            TmjLayer::Other => {}
+        }
+    }
+    Ok(())
+}
+
+fn parse_tmx(bytes: &[u8]) -> Result<TiledMap, MapError> {
+    let mut tiled_map = TiledMap::default();
+    // Encoding, compression and contents of the layer data currently being read, if any.
+    let mut data: Option<(Option<String>, Option<String>, String)> = None;
+    // How deeply nested the current element is, where the map itself is 1.
+    let mut depth = 0;
+
+    for event in EventReader::new(bytes) {
+        match event.map_err(|err| MapError::Tiled(err.to_string()))? {
+            XmlEvent::StartElement {
+                name, attributes, ..
+            } => {
+                depth += 1;
+                match name.local_name.as_str() {
+                    "map" => {
+                        if attribute(&attributes, "infinite") == Some("1") {
+                            return Err(MapError::Tiled(
+                                "infinite maps are not supported".to_string(),
+                            ));
+                        }
+                        tiled_map.width = number_attribute(&attributes, "width")?;
+                        tiled_map.height = number_attribute(&attributes, "height")?;
+                        tiled_map.tile_width = number_attribute(&attributes, "tilewidth")?;
+                        tiled_map.tile_height = number_attribute(&attributes, "tileheight")?;
+                    }
+                    "data" => {
+                        tiled_map.layers.push(vec![]);
+                        data = Some((
+                            attribute(&attributes, "encoding").map(str::to_string),
+                            attribute(&attributes, "compression").map(str::to_string),
+                            String::new(),
+                        ));
+                    }
+                    "tile" if data.is_some() => {
+                        let gid = match attribute(&attributes, "gid") {
+                            Some(_) => number_attribute(&attributes, "gid")?,
+                            None => 0,
+                        };
+                        tiled_map.layers.last_mut().unwrap().push(gid);
+                    }
+                    "object" => {
+                        tiled_map.objects.push(TiledObject {
+                            id: number_attribute(&attributes, "id")?,
+                            kind: object_kind(
+                                attribute(&attributes, "type")
+                                    .unwrap_or_default()
+                                    .to_string(),
+                                attribute(&attributes, "class")
+                                    .unwrap_or_default()
+                                    .to_string(),
+                                attribute(&attributes, "name")
+                                    .unwrap_or_default()
+                                    .to_string(),
+                            ),
+                            x: number_attribute(&attributes, "x")?,
+                            y: number_attribute(&attributes, "y")?,
+                            width: optional_number_attribute(&attributes, "width")?,
+                            height: optional_number_attribute(&attributes, "height")?,
+                            gid: match attribute(&attributes, "gid") {
+                                Some(_) => Some(number_attribute(&attributes, "gid")?),
+                                None => None,
+                            },
+                        });
+                    }
+                    "chunk" => {
+                        return Err(MapError::Tiled(
+                            "infinite maps are not supported".to_string(),
+                        ));
+                    }
+                    // Only the map's own properties, not those of its layers or objects.
+                    "property" if depth == 3 => {
+                        tiled_map.properties.push((
+                            attribute(&attributes, "name")
+                                .unwrap_or_default()
+                                .to_string(),
+                            attribute(&attributes, "value")
+                                .unwrap_or_default()
+                                .to_string(),
+                        ));
+                    }
+                    _ => {}
+                }
+            }
+            XmlEvent::Characters(text) => {
+                if let Some((_, _, contents)) = data.as_mut() {
+                    contents.push_str(&text);
+                }
+            }
+            XmlEvent::EndElement { name } => {
+                depth -= 1;
+                if name.local_name == "data" {
+                    if let Some((Some(encoding), compression, contents)) = data.take() {
+                        *tiled_map.layers.last_mut().unwrap() =
+                            decode_layer_data(&contents, Some(&encoding), compression.as_deref())?;
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+
+    Ok(tiled_map)
+}
+
+fn attribute<'a>(attributes: &'a [OwnedAttribute], name: &str) -> Option<&'a str> {
+    attributes
+        .iter()
+        .find(|attribute| attribute.name.local_name == name)
+        .map(|attribute| attribute.value.as_str())
+}
+
+fn number_attribute<T: str::FromStr>(
+    attributes: &[OwnedAttribute],
+    name: &str,
+) -> Result<T, MapError> {
+    let value = attribute(attributes, name)
+        .ok_or_else(|| MapError::Tiled(format!("missing `{}` attribute", name)))?;
+    value
+        .parse()
+        .map_err(|_| MapError::Tiled(format!("invalid `{}` attribute `{}`", name, value)))
+}
+
+fn optional_number_attribute(attributes: &[OwnedAttribute], name: &str) -> Result<f32, MapError> {
+    match attribute(attributes, name) {
+        Some(_) => number_attribute(attributes, name),
+        None => Ok(0.),
+    }
+}
+
+/// Picks what an object represents, preferring its type or class over its name.
+fn object_kind(kind: String, class: String, name: String) -> String {
+    [kind, class, name]
+        .into_iter()
+        .find(|kind| !kind.is_empty())
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A 4x3 map whose walls surround two cells of water, holding the player and the portal.
+    fn tmx(data: &str) -> String {
+        format!(
+            r#"<?xml version="1.0" encoding="UTF-8"?>
+<map version="1.9" width="4" height="3" tilewidth="16" tileheight="16" infinite="0">
+ <layer id="1" name="Walls" width="4" height="3">
+  {}
+ </layer>
+ <objectgroup id="2" name="Objects">
+  <object id="1" type="player" x="16" y="16" width="16" height="16"/>
+  <object id="2" type="portal" x="32" y="16" width="16" height="16"/>
+ </objectgroup>
+</map>"#,
+            data
+        )
+    }
+
+    fn load_tmx(tmx: &str) -> Result<Map, MapError> {
+        parse_tmx(tmx.as_bytes()).and_then(TiledMap::into_map)
+    }
+
+    fn load_tmj(tmj: &str) -> Result<Map, MapError> {
+        parse_tmj(tmj.as_bytes()).and_then(TiledMap::into_map)
+    }
+
+    fn assert_walls(map: &Map) {
+        assert_eq!((map.width, map.height), (4, 3));
+        for row in [0, 2] {
+            assert!(map.tiles[row].iter().all(|&tile| tile == Tile::Wall));
+        }
+        assert_eq!(
+            map.tiles[1],
+            vec![Tile::Wall, Tile::Empty, Tile::Empty, Tile::Wall]
+        );
+        assert_eq!(map.player_spawn, (1, 1));
+        assert_eq!(map.portal_spawn, (2, 1));
+    }
+
+    #[test]
+    fn loads_csv_layer() {
+        let map = load_tmx(&tmx(r#"<data encoding="csv">
+1,1,1,1,
+1,0,0,1,
+1,1,1,1
+</data>"#))
+        .unwrap();
+        assert_walls(&map);
+    }
+
+    #[test]
+    fn loads_base64_layer() {
+        let data = "AQAAAAEAAAABAAAAAQAAAAEAAAAAAAAAAAAAAAEAAAABAAAAAQAAAAEAAAABAAAA";
+        let map = load_tmx(&tmx(&format!(r#"<data encoding="base64">{}</data>"#, data))).unwrap();
+        assert_walls(&map);
+
+        let map = load_tmj(&format!(
+            r#"{{
+                "width": 4, "height": 3, "tilewidth": 16, "tileheight": 16,
+                "layers": [
+                    {{"type": "tilelayer", "encoding": "base64", "data": "{}"}},
+                    {{"type": "objectgroup", "objects": [
+                        {{"id": 1, "type": "player", "x": 16, "y": 16, "width": 16, "height": 16}},
+                        {{"id": 2, "class": "portal", "x": 32, "y": 16, "width": 16, "height": 16}}
+                    ]}}
+                ]
+            }}"#,
+            data
+        ))
+        .unwrap();
+        assert_walls(&map);
+    }
+
+    #[test]
+    fn rejects_compressed_layer() {
+        let error = load_tmx(&tmx(
+            r#"<data encoding="base64" compression="zlib">eJxjZGBgYAQAAA0AAw==</data>"#,
+        ))
+        .unwrap_err();
+        assert!(error.to_string().contains("zlib compressed"), "{}", error);
+    }
+
+    #[test]
+    fn rejects_infinite_map() {
+        let error = load_tmx(
+            &tmx(r#"<data encoding="csv">1,1,1,1,1,0,0,1,1,1,1,1</data>"#)
+                .replace(r#"infinite="0""#, r#"infinite="1""#),
+        )
+        .unwrap_err();
+        assert!(error.to_string().contains("infinite"), "{}", error);
+
+        let error = load_tmj(
+            r#"{"width": 4, "height": 3, "tilewidth": 16, "tileheight": 16, "infinite": true,
+                "layers": []}"#,
+        )
+        .unwrap_err();
+        assert!(error.to_string().contains("infinite"), "{}", error);
+    }
+
+    #[test]
+    fn rejects_unknown_tiles_and_objects() {
+        let error = load_tmx(&tmx(
+            r#"<data encoding="csv">1,1,1,1,1,0,0,1,1,1,1,wall</data>"#,
+        ))
+        .unwrap_err();
+        assert!(
+            error.to_string().contains("invalid tile id `wall`"),
+            "{}",
+            error
+        );
+
+        let error = load_tmx(
+            &tmx(r#"<data encoding="csv">1,1,1,1,1,0,0,1,1,1,1,1</data>"#)
+                .replace(r#"type="portal""#, r#"type="shark""#),
+        )
+        .unwrap_err();
+        assert!(
+            matches!(&error, MapError::UnknownObject { id: 2, kind } if kind == "shark"),
+            "{:?}",
+            error
+        );
+    }
+}
@@ -0,0 +1,427 @@
+use super::map::{Map, MapLoadErrors, Tile, TILE_HEIGHT, TILE_WIDTH};
+use bevy::{
+    asset::{AssetLoader, AssetPath, LoadContext, LoadedAsset},
+    prelude::*,
+    reflect::TypeUuid,
+    utils::BoxedFuture,
+};
+use std::str::{self, FromStr};
+use thiserror::Error;
+
+/// Neighbor offsets in the order they are written in a rule: clockwise, starting from the top.
+const NEIGHBORS: [(i32, i32); 8] = [
+    (0, 1),
+    (1, 1),
+    (1, 0),
+    (1, -1),
+    (0, -1),
+    (-1, -1),
+    (-1, 0),
+    (-1, 1),
+];
+
+/// A tile sheet and the autotiling rules that choose which of its tiles to draw for each cell
+/// of a map, loaded from a `.tileset` file.
+#[derive(Debug, TypeUuid)]
+#[uuid = "8d2f6b3a-51c7-4e0b-9a64-0c3e7d1b5f92"]
+pub struct Tileset {
+    pub atlas: Handle<TextureAtlas>,
+    rules: Vec<TileRule>,
+}
+
+impl Tileset {
+    /// The atlas index of the first rule matching a cell and its neighbors, or the first tile
+    /// of the sheet if none do. Anything beyond the edge of the map counts as a wall, and air
+    /// pockets are drawn like water unless a rule says otherwise.
+    pub fn index(&self, map: &Map, column: usize, row: usize) -> usize {
+        let mut walls = 0;
+        for (bit, (dx, dy)) in NEIGHBORS.iter().enumerate() {
+            let (x, y) = (column as i32 + dx, row as i32 + dy);
+            let is_wall = x < 0
+                || y < 0
+                || x >= map.width as i32
+                || y >= map.height as i32
+                || map.tiles[y as usize][x as usize] == Tile::Wall;
+            if is_wall {
+                walls |= 1 << bit;
+            }
+        }
+
+        let matching = |tile| {
+            self.rules
+                .iter()
+                .find(|rule| rule.tile == tile && walls & rule.mask == rule.walls)
+        };
+        let tile = map.tiles[row][column];
+        let rule = match tile {
+            Tile::Air => matching(Tile::Air).or_else(|| matching(Tile::Empty)),
+            _ => matching(tile),
+        };
+        rule.map_or(0, |rule| rule.index)
+    }
+
+    /// A tileset without rules, drawing every cell with the first tile of the sheet.
+    #[cfg(test)]
+    pub fn untiled(atlas: Handle<TextureAtlas>) -> Self {
+        Tileset {
+            atlas,
+            rules: Vec::new(),
+        }
+    }
+}
+
+#[derive(Debug)]
+struct TileRule {
+    tile: Tile,
+    /// The neighbors this rule cares about, one bit each in `NEIGHBORS` order.
+    mask: u8,
+    /// Which of the neighbors in `mask` must be walls.
+    walls: u8,
+    index: usize,
+}
+
+/// Reasons a tileset can be rejected. Lines are 1-based positions in the `.tileset` file.
+#[derive(Debug, Error)]
+pub enum TilesetError {
+    #[error("tileset is not valid UTF-8: {0}")]
+    InvalidUtf8(#[from] str::Utf8Error),
+    #[error("line {line}: unknown setting `{setting}`")]
+    UnknownSetting { line: usize, setting: String },
+    #[error("line {line}: expected `{expected}`")]
+    Malformed { line: usize, expected: &'static str },
+    #[error("line {line}: tile {index} is outside of the {count} tile sheet")]
+    IndexOutOfRange {
+        line: usize,
+        index: usize,
+        count: usize,
+    },
+    #[error("tileset has no `image`")]
+    MissingImage,
+    #[error("tileset has no `grid`")]
+    MissingGrid,
+}
+
+/// The contents of a `.tileset` file, before its image is loaded.
+struct TilesetFile {
+    image: String,
+    columns: usize,
+    rows: usize,
+    rules: Vec<TileRule>,
+}
+
+impl FromStr for TilesetFile {
+    type Err = TilesetError;
+
+    fn from_str(tileset_str: &str) -> Result<Self, Self::Err> {
+        let mut image = None;
+        let mut grid = None;
+        let mut rules = vec![];
+
+        for (i, line) in tileset_str.lines().enumerate() {
+            let line_number = i + 1;
+            let words = line.split_whitespace().collect::<Vec<&str>>();
+            match words.as_slice() {
+                [] => {}
+                [comment, ..] if comment.starts_with('#') => {}
+                ["image", path] => image = Some(path.to_string()),
+                ["image", ..] => {
+                    return Err(TilesetError::Malformed {
+                        line: line_number,
+                        expected: "image <path>",
+                    })
+                }
+                ["grid", columns, rows] => match (columns.parse(), rows.parse()) {
+                    (Ok(columns), Ok(rows)) => grid = Some((columns, rows)),
+                    _ => {
+                        return Err(TilesetError::Malformed {
+                            line: line_number,
+                            expected: "grid <columns> <rows>",
+                        })
+                    }
+                },
+                ["rule", tile, neighbors, index] => {
+                    let rule =
+                        parse_rule(tile, neighbors, index).ok_or(TilesetError::Malformed {
+                            line: line_number,
+                            expected: "rule <wall|water|air> <neighbors> <index>",
+                        })?;
+                    rules.push((line_number, rule));
+                }
+                [setting, ..] => {
+                    return Err(TilesetError::UnknownSetting {
+                        line: line_number,
+                        setting: setting.to_string(),
+                    })
+                }
+            }
+        }
+
+        let image = image.ok_or(TilesetError::MissingImage)?;
+        let (columns, rows) = grid.ok_or(TilesetError::MissingGrid)?;
+        let count = columns * rows;
+        if let Some((line, rule)) = rules.iter().find(|(_, rule)| rule.index >= count) {
+            return Err(TilesetError::IndexOutOfRange {
+                line: *line,
+                index: rule.index,
+                count,
+            });
+        }
+
+        Ok(TilesetFile {
+            image,
+            columns,
+            rows,
+            rules: rules.into_iter().map(|(_, rule)| rule).collect(),
+        })
+    }
+}
+
+fn parse_rule(tile: &str, neighbors: &str, index: &str) -> Option<TileRule> {
+    let tile = match tile {
+        "wall" => Tile::Wall,
+        "water" => Tile::Empty,
+        "air" => Tile::Air,
+        _ => return None,
+    };
+    if neighbors.chars().count() != NEIGHBORS.len() {
+        return None;
+    }
+
+    let mut mask = 0;
+    let mut walls = 0;
+    for (bit, c) in neighbors.chars().enumerate() {
+        match c {
+            '1' => {
+                mask |= 1 << bit;
+                walls |= 1 << bit;
+            }
+            '0' => mask |= 1 << bit,
+            '*' => {}
+            _ => return None,
+        }
+    }
+
+    Some(TileRule {
+        tile,
+        mask,
+        walls,
+        index: index.parse().ok()?,
+    })
+}
+
+pub struct TilesetLoader {
+    errors: MapLoadErrors,
+}
+
+impl FromWorld for TilesetLoader {
+    fn from_world(world: &mut World) -> Self {
+        TilesetLoader {
+            errors: world
+                .get_resource_or_insert_with(MapLoadErrors::default)
+                .clone(),
+        }
+    }
+}
+
+impl AssetLoader for TilesetLoader {
+    fn load<'a>(
+        &'a self,
+        bytes: &'a [u8],
+        load_context: &'a mut LoadContext,
+    ) -> BoxedFuture<'a, Result<(), bevy::asset::Error>> {
+        Box::pin(async move {
+            let tileset_file = match str::from_utf8(bytes)
+                .map_err(TilesetError::from)
+                .and_then(str::parse::<TilesetFile>)
+            {
+                Ok(tileset_file) => tileset_file,
+                Err(err) => {
+                    self.errors
+                        .push(format!("{}: {}", load_context.path().display(), err));
+                    return Err(err.into());
+                }
+            };
+
+            // The image path is relative to the tileset, like the levels of a campaign.
+            let dir = load_context.path().parent().unwrap().to_path_buf();
+            let image_path = AssetPath::from(dir.join(&tileset_file.image));
+            let atlas = TextureAtlas::from_grid(
+                load_context.get_handle(image_path.clone()),
+                Vec2::new(TILE_WIDTH, TILE_HEIGHT),
+                tileset_file.columns,
+                tileset_file.rows,
+            );
+            let atlas = load_context.set_labeled_asset("atlas", LoadedAsset::new(atlas));
+
+            let tileset = Tileset {
+                atlas,
+                rules: tileset_file.rules,
+            };
+            load_context.set_default_asset(LoadedAsset::new(tileset).with_dependency(image_path));
+            Ok(())
+        })
+    }
+
+    fn extensions(&self) -> &[&str] {
+        &["tileset"]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tileset(rules: &str) -> Tileset {
+        let file = format!("image tiles.png\ngrid 4 4\n{}", rules)
+            .parse::<TilesetFile>()
+            .unwrap();
+        Tileset {
+            atlas: default(),
+            rules: file.rules,
+        }
+    }
+
+    fn map(map: &str) -> Map {
+        map.parse().unwrap()
+    }
+
+    #[test]
+    fn rules_match_walls_among_the_neighbors() {
+        let tileset = tileset(
+            "rule wall 0******* 1
+             rule water **1***** 2
+             rule water ******** 3",
+        );
+        let map = map("1 1 1 1
+                       1 P X 1
+                       1 0 0 1
+                       1 1 1 1");
+        // The wall below the spawn has water above it, the one in the corner doesn't.
+        assert_eq!(tileset.index(&map, 1, 0), 1);
+        assert_eq!(tileset.index(&map, 0, 0), 0);
+        // Only the water next to the right-hand wall matches its rule.
+        assert_eq!(tileset.index(&map, 2, 1), 2);
+        assert_eq!(tileset.index(&map, 1, 1), 3);
+    }
+
+    #[test]
+    fn first_matching_rule_wins() {
+        let tileset = tileset(
+            "rule water ******** 1
+             rule water 1******* 2",
+        );
+        let map = map("1 1 1
+                       1 P 1
+                       1 X 1
+                       1 1 1");
+        assert_eq!(tileset.index(&map, 1, 2), 1);
+    }
+
+    #[test]
+    fn cells_beyond_the_map_count_as_walls() {
+        let tileset = tileset(
+            "rule wall 11111111 1
+             rule wall ******** 2",
+        );
+        let map = map("1 1 1 1 1
+                       1 P 0 X 1
+                       1 1 1 1 1
+                       1 1 1 1 1");
+        assert_eq!(tileset.index(&map, 0, 0), 1);
+        assert_eq!(tileset.index(&map, 4, 0), 1);
+        assert_eq!(tileset.index(&map, 1, 3), 2);
+    }
+
+    #[test]
+    fn air_falls_back_to_water_rules() {
+        let map = map("1 1 1 1
+                       1 P A 1
+                       1 X 0 1
+                       1 1 1 1");
+        assert_eq!(tileset("rule water ******** 3").index(&map, 2, 2), 3);
+        let tileset = tileset(
+            "rule air ******** 2
+             rule water ******** 3",
+        );
+        assert_eq!(tileset.index(&map, 2, 2), 2);
+        assert_eq!(tileset.index(&map, 2, 1), 3);
+    }
+
+    #[test]
+    fn shipped_rules_draw_edges_and_corners() {
+        let tileset = tileset(include_str!("../assets/textures/tiles.tileset"));
+        let map = map("1 1 1 1 1 1
+                       1 1 1 1 1 1
+                       1 1 0 0 0 1
+                       1 1 0 1 0 1
+                       1 P 0 0 X 1
+                       1 1 1 1 1 1");
+        let index = |column, row| tileset.index(&map, column, row);
+        assert_eq!(index(0, 5), 4, "surrounded by walls");
+        assert_eq!(index(3, 0), 0, "floor under the water");
+        assert_eq!(index(3, 4), 5, "ceiling over the water");
+        assert_eq!(index(1, 3), 7, "wall to the left of the water");
+        assert_eq!(index(5, 3), 6, "wall to the right of the water");
+        assert_eq!(index(3, 2), 8, "pillar in the water");
+        assert_eq!(index(1, 4), 14, "water only below and to the right");
+        assert_eq!(index(0, 0), 12, "water only above and to the right");
+        assert_eq!(index(2, 2), 3, "water");
+    }
+
+    #[test]
+    fn malformed_rules_report_their_line() {
+        for rule in [
+            "rule lava ******** 0",
+            "rule wall ******* 0",
+            "rule wall ***x**** 0",
+            "rule wall ******** first",
+        ] {
+            let error = format!("image tiles.png\ngrid 4 4\n{}", rule)
+                .parse::<TilesetFile>()
+                .err()
+                .unwrap();
+            assert!(
+                matches!(error, TilesetError::Malformed { line: 3, .. }),
+                "{}: {:?}",
+                rule,
+                error
+            );
+        }
+    }
+
+    #[test]
+    fn rule_beyond_the_sheet_is_rejected() {
+        let error = "image tiles.png\ngrid 2 2\n\nrule wall ******** 4"
+            .parse::<TilesetFile>()
+            .err()
+            .unwrap();
+        assert!(
+            matches!(
+                error,
+                TilesetError::IndexOutOfRange {
+                    line: 4,
+                    index: 4,
+                    count: 4
+                }
+            ),
+            "{:?}",
+            error
+        );
+    }
+
+    #[test]
+    fn tileset_needs_image_and_grid() {
+        let missing_image = "grid 2 2".parse::<TilesetFile>().err().unwrap();
+        assert!(matches!(missing_image, TilesetError::MissingImage));
+        let missing_grid = "image tiles.png".parse::<TilesetFile>().err().unwrap();
+        assert!(matches!(missing_grid, TilesetError::MissingGrid));
+        let unknown = "image tiles.png\ngrid 2 2\nscale 2"
+            .parse::<TilesetFile>()
+            .err()
+            .unwrap();
+        assert!(matches!(
+            unknown,
+            TilesetError::UnknownSetting { line: 3, .. }
+        ));
+    }
+}
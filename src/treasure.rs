@@ -1,42 +1,143 @@
 use super::app::AppState;
 use super::assets::GameAssets;
-use super::map::{Map, TILE_HEIGHT, TILE_WIDTH};
+use super::campaign::{CurrentLevel, LevelEntity};
+use super::map::{Map, MapEntity, MapReloaded, TILE_HEIGHT, TILE_WIDTH};
 use bevy::prelude::*;
 use bevy_rapier2d::prelude::*;
 
 // Make it a little harder to grab treasure
 const TREASURE_SHRINKAGE: f32 = 4.;
 
+/// A piece of treasure, remembering which cell it was placed in so it can be put back.
 #[derive(Component)]
-pub struct Treasure;
+pub struct Treasure {
+    pub cell: (u32, u32),
+}
+
+/// Cells of the treasure collected so far in this attempt at the level. Reloading the map leaves
+/// them out, so editing a level doesn't hand back treasure the player already has.
+#[derive(Default)]
+pub struct CollectedTreasure(pub Vec<(u32, u32)>);
 
 pub struct TreasurePlugin;
 
 impl Plugin for TreasurePlugin {
     fn build(&self, app: &mut App) {
-        app.add_system_set(SystemSet::on_enter(AppState::Game).with_system(setup_treasure));
+        app.init_resource::<CollectedTreasure>()
+            .add_system_set(SystemSet::on_enter(AppState::Game).with_system(setup_treasure))
+            .add_system_set(SystemSet::on_update(AppState::Game).with_system(reload_treasure));
+    }
+}
+
+fn setup_treasure(
+    mut commands: Commands,
+    mut collected: ResMut<CollectedTreasure>,
+    game_assets: Res<GameAssets>,
+    maps: Res<Assets<Map>>,
+    level: Res<CurrentLevel>,
+) {
+    let map = maps.get(&level.map).unwrap();
+    collected.0.clear();
+    spawn_treasure(&mut commands, map, &[], &game_assets);
+}
+
+fn reload_treasure(
+    mut commands: Commands,
+    mut map_reloaded_events: EventReader<MapReloaded>,
+    collected: Res<CollectedTreasure>,
+    game_assets: Res<GameAssets>,
+    maps: Res<Assets<Map>>,
+    level: Res<CurrentLevel>,
+) {
+    if map_reloaded_events.iter().count() == 0 {
+        return;
+    }
+    let map = maps.get(&level.map).unwrap();
+    spawn_treasure(&mut commands, map, &collected.0, &game_assets);
+}
+
+/// Spawns the map's treasure, except in the cells listed as already collected.
+fn spawn_treasure(
+    commands: &mut Commands,
+    map: &Map,
+    collected: &[(u32, u32)],
+    game_assets: &GameAssets,
+) {
+    for &treasure in map.treasures.iter() {
+        if !collected.contains(&treasure) {
+            spawn_treasure_at(commands, treasure, game_assets);
+        }
     }
 }
 
-fn setup_treasure(mut commands: Commands, game_assets: Res<GameAssets>, maps: Res<Assets<Map>>) {
-    let map = maps.get(&game_assets.map).unwrap();
-
-    for treasure in map.treasures.iter() {
-        commands
-            .spawn_bundle(SpriteBundle {
-                texture: game_assets.coin_image.clone(),
-                transform: Transform::from_translation(Vec3::new(
-                    TILE_WIDTH * treasure.0 as f32,
-                    TILE_HEIGHT * treasure.1 as f32,
-                    1.,
-                )),
-                ..default()
-            })
-            .insert(Treasure)
-            .insert(Collider::cuboid(
-                TILE_WIDTH / 2. - TREASURE_SHRINKAGE,
-                TILE_HEIGHT / 2. - TREASURE_SHRINKAGE,
-            ))
-            .insert(Sensor);
+pub fn spawn_treasure_at(commands: &mut Commands, cell: (u32, u32), game_assets: &GameAssets) {
+    commands
+        .spawn_bundle(SpriteBundle {
+            texture: game_assets.coin_image.clone(),
+            transform: Transform::from_translation(Vec3::new(
+                TILE_WIDTH * cell.0 as f32,
+                TILE_HEIGHT * cell.1 as f32,
+                1.,
+            )),
+            ..default()
+        })
+        .insert(Treasure { cell })
+        .insert(LevelEntity)
+        .insert(MapEntity)
+        .insert(Collider::cuboid(
+            TILE_WIDTH / 2. - TREASURE_SHRINKAGE,
+            TILE_HEIGHT / 2. - TREASURE_SHRINKAGE,
+        ))
+        .insert(Sensor);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::harness::{Harness, SWIM_RIGHT};
+    use crate::player::Player;
+
+    fn treasure_cells(world: &mut World) -> Vec<(u32, u32)> {
+        world
+            .query::<&Treasure>()
+            .iter(world)
+            .map(|treasure| treasure.cell)
+            .collect()
+    }
+
+    #[test]
+    fn reloading_map_keeps_collected_treasure_collected() {
+        let mut harness = Harness::new(
+            "1 1 1 1 1 1 1 1 1 1 1
+             1 0 0 0 0 0 0 0 0 0 1
+             1 P T 0 0 0 0 0 T X 1
+             1 1 1 1 1 1 1 1 1 1 1",
+        );
+        // Swim well clear of the first treasure, so it can't just be collected again.
+        harness.run_until(360, SWIM_RIGHT, |world| {
+            let player = world
+                .query_filtered::<&Transform, With<Player>>()
+                .single(world);
+            player.translation.x > TILE_WIDTH * 4.
+        });
+        assert_eq!(treasure_cells(&mut harness.app.world), [(8, 1)]);
+
+        let map = harness.app.world.resource::<CurrentLevel>().map.clone();
+        // Touching the map asset is all a hot reload needs.
+        harness
+            .app
+            .world
+            .resource_mut::<Assets<Map>>()
+            .get_mut(&map)
+            .unwrap();
+        harness.run_until(5, default(), |world| {
+            world
+                .resource::<Events<MapReloaded>>()
+                .iter_current_update_events()
+                .count()
+                > 0
+        });
+        harness.run(2, default());
+        assert_eq!(treasure_cells(&mut harness.app.world), [(8, 1)]);
     }
 }